@@ -0,0 +1,35 @@
+use tokio::time::Duration;
+
+/// Exponential backoff schedule for gateway reconnects: delay doubles
+/// with each consecutive failure starting from one second, capped at a
+/// minute, and resets once a connection succeeds again.
+pub struct ExponentialBackoff {
+    base_secs: f64,
+    max_secs: f64,
+    attempt: u32,
+}
+
+impl ExponentialBackoff {
+    pub fn new() -> Self {
+        ExponentialBackoff { base_secs: 1.0, max_secs: 60.0, attempt: 0 }
+    }
+
+    /// The delay to wait before the next retry, advancing the schedule.
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = (self.base_secs * 2f64.powi(self.attempt as i32)).min(self.max_secs);
+        self.attempt += 1;
+        Duration::from_secs_f64(delay)
+    }
+
+    /// Call after a connection is established successfully so the next
+    /// failure starts the schedule over instead of compounding.
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+}
+
+impl Default for ExponentialBackoff {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,12 +1,289 @@
-use pyo3::prelude::*;
-use std::sync::Arc;
-use crate::http::HTTPClient;
-use crate::gateway::Gateway;
-use crate::state::State;
+use crate::_types::Snowflake;
 use crate::enums::Intents;
 use crate::errors::DiscordError;
+use crate::gateway::{Gateway, GatewayCompression};
+use crate::http::HTTPClient;
+use crate::shard::ShardManager;
+use crate::state::State;
+use dashmap::DashMap;
+use pyo3::prelude::*;
+use pyo3::types::{PyCFunction, PyTuple};
+use std::sync::{Arc, OnceLock};
 use tokio::sync::RwLock;
 
+/// Maps a gateway dispatch `t` value to the listener name Python code
+/// registers against (mirrors discord.py's `on_xxx` convention).
+fn handler_name_for_event(event_type: &str) -> Option<&'static str> {
+    Some(match event_type {
+        "READY" => "on_ready",
+        "RESUMED" => "on_resume",
+        "MESSAGE_CREATE" => "on_message",
+        "MESSAGE_UPDATE" => "on_message_edit",
+        "MESSAGE_DELETE" => "on_message_delete",
+        "GUILD_CREATE" => "on_guild_join",
+        "GUILD_UPDATE" => "on_guild_update",
+        "GUILD_DELETE" => "on_guild_remove",
+        "CHANNEL_CREATE" => "on_guild_channel_create",
+        "CHANNEL_UPDATE" => "on_guild_channel_update",
+        "CHANNEL_DELETE" => "on_guild_channel_delete",
+        "GUILD_MEMBER_ADD" => "on_member_join",
+        "GUILD_MEMBER_REMOVE" => "on_member_remove",
+        "GUILD_MEMBER_UPDATE" => "on_member_update",
+        "GUILD_ROLE_CREATE" => "on_guild_role_create",
+        "GUILD_ROLE_UPDATE" => "on_guild_role_update",
+        "GUILD_ROLE_DELETE" => "on_guild_role_delete",
+        "TYPING_START" => "on_typing",
+        "PRESENCE_UPDATE" => "on_presence_update",
+        "VOICE_STATE_UPDATE" => "on_voice_state_update",
+        "INTERACTION_CREATE" => "on_interaction",
+        _ => return None,
+    })
+}
+
+/// Registry of Python listeners, keyed by `on_xxx` event name.
+///
+/// Shared between `Client` and `ClientInternal` so listeners registered
+/// before or after `run()` is called all see the same map.
+#[derive(Clone)]
+pub(crate) struct EventHandlers {
+    listeners: Arc<DashMap<String, Vec<Py<PyAny>>>>,
+    /// Listeners registered against the raw gateway `t` value (e.g.
+    /// `"MESSAGE_CREATE"`) via `Client.on`, bypassing the `on_xxx` mapping
+    /// `listeners` uses.
+    raw_listeners: Arc<DashMap<String, Vec<Py<PyAny>>>>,
+    /// Lazily-built coroutine that awaits a handler and logs any exception
+    /// it raises, so one broken listener can't tear down the event loop.
+    safe_runner: Arc<OnceLock<Py<PyAny>>>,
+    /// The asyncio event loop listeners run on, captured once by
+    /// `Client::run` before the gateway connects. `dispatch` runs on a
+    /// tokio worker thread, so tasks must be handed to this loop with a
+    /// thread-safe scheduling call rather than `create_task`.
+    event_loop: Arc<OnceLock<Py<PyAny>>>,
+}
+
+impl EventHandlers {
+    fn new() -> Self {
+        EventHandlers {
+            listeners: Arc::new(DashMap::new()),
+            raw_listeners: Arc::new(DashMap::new()),
+            safe_runner: Arc::new(OnceLock::new()),
+            event_loop: Arc::new(OnceLock::new()),
+        }
+    }
+
+    /// Record the event loop listeners should be scheduled onto. Only the
+    /// first call takes effect.
+    pub(crate) fn set_event_loop(&self, event_loop: Py<PyAny>) {
+        let _ = self.event_loop.set(event_loop);
+    }
+
+    fn add(&self, name: String, coro_fn: Py<PyAny>) {
+        self.listeners
+            .entry(name)
+            .or_insert_with(Vec::new)
+            .push(coro_fn);
+    }
+
+    fn add_raw(&self, name: String, coro_fn: Py<PyAny>) {
+        self.raw_listeners
+            .entry(name)
+            .or_insert_with(Vec::new)
+            .push(coro_fn);
+    }
+
+    fn remove(&self, py: Python, name: &str, coro_fn: &Py<PyAny>) {
+        if let Some(mut entry) = self.listeners.get_mut(name) {
+            entry.retain(|f| !f.is(coro_fn.bind(py)));
+        }
+    }
+
+    fn get_runner(&self, py: Python) -> PyResult<Py<PyAny>> {
+        if let Some(runner) = self.safe_runner.get() {
+            return Ok(runner.clone_ref(py));
+        }
+        let runner = build_safe_runner(py)?;
+        Ok(self.safe_runner.get_or_init(|| runner).clone_ref(py))
+    }
+
+    /// Like `dispatch`, but tags `data` with the originating shard id first,
+    /// so a multi-shard bot's listeners can tell which connection an event
+    /// came from.
+    pub(crate) fn dispatch_with_shard(
+        &self,
+        shard_id: u32,
+        event_type: &str,
+        data: &serde_json::Value,
+    ) {
+        let mut tagged = data.clone();
+        if let serde_json::Value::Object(ref mut map) = tagged {
+            map.insert("shard_id".to_string(), serde_json::Value::from(shard_id));
+        }
+        self.dispatch(event_type, &tagged);
+    }
+
+    /// Dispatch `event_type` (a raw gateway `t` value) to every registered
+    /// listener, converting `data` into a plain Python object via `json.loads`.
+    pub(crate) fn dispatch(&self, event_type: &str, data: &serde_json::Value) {
+        if let Some(entry) = self.raw_listeners.get(event_type) {
+            let raw: Vec<Py<PyAny>> = entry.clone();
+            drop(entry);
+            for listener in raw {
+                if let Err(e) = self.schedule(&listener, data) {
+                    eprintln!("Error dispatching {}: {}", event_type, e);
+                }
+            }
+        }
+
+        let name = match handler_name_for_event(event_type) {
+            Some(n) => n.to_string(),
+            None => {
+                // Unknown dispatch: still surface it so bots can introspect
+                // events the library doesn't model yet.
+                self.dispatch_raw("on_socket_raw_receive", event_type, data);
+                return;
+            }
+        };
+
+        let listeners: Vec<Py<PyAny>> = match self.listeners.get(&name) {
+            Some(entry) => entry.clone(),
+            None => return,
+        };
+
+        for listener in listeners {
+            if let Err(e) = self.schedule(&listener, data) {
+                eprintln!("Error dispatching {}: {}", name, e);
+            }
+        }
+    }
+
+    fn dispatch_raw(&self, name: &str, event_type: &str, data: &serde_json::Value) {
+        let listeners: Vec<Py<PyAny>> = match self.listeners.get(name) {
+            Some(entry) => entry.clone(),
+            None => return,
+        };
+
+        for listener in listeners {
+            if let Err(e) = self.schedule_raw(&listener, event_type, data) {
+                eprintln!("Error dispatching {}: {}", name, e);
+            }
+        }
+    }
+
+    fn schedule(&self, listener: &Py<PyAny>, data: &serde_json::Value) -> PyResult<()> {
+        Python::with_gil(|py| {
+            let runner = self.get_runner(py)?;
+            let arg = json_to_py(py, data)?;
+            let coro = listener.call1(py, (arg,))?;
+            let wrapped = runner.call1(py, (coro,))?;
+            self.submit(py, wrapped)
+        })
+    }
+
+    fn schedule_raw(
+        &self,
+        listener: &Py<PyAny>,
+        event_type: &str,
+        data: &serde_json::Value,
+    ) -> PyResult<()> {
+        Python::with_gil(|py| {
+            let runner = self.get_runner(py)?;
+            let arg = json_to_py(py, data)?;
+            let coro = listener.call1(py, (event_type, arg))?;
+            let wrapped = runner.call1(py, (coro,))?;
+            self.submit(py, wrapped)
+        })
+    }
+
+    /// Hand `coro` to the captured event loop via `run_coroutine_threadsafe`,
+    /// which is safe to call from the tokio worker thread `dispatch` runs on
+    /// (unlike `create_task`, which requires the calling thread to already be
+    /// running that loop).
+    fn submit(&self, py: Python, coro: Py<PyAny>) -> PyResult<()> {
+        let event_loop = self.event_loop.get().ok_or_else(|| {
+            pyo3::exceptions::PyRuntimeError::new_err(
+                "no event loop registered; Client.run() must be running before events dispatch",
+            )
+        })?;
+        let asyncio = py.import_bound("asyncio")?;
+        asyncio.call_method1("run_coroutine_threadsafe", (coro, event_loop.bind(py)))?;
+        Ok(())
+    }
+}
+
+/// Turns `value` into a plain Python object (dict/list/str/...) via `json.loads`,
+/// since most event payloads don't yet have a dedicated model type.
+fn json_to_py(py: Python, value: &serde_json::Value) -> PyResult<Py<PyAny>> {
+    let json = py.import_bound("json")?;
+    let loaded = json.call_method1("loads", (value.to_string(),))?;
+    Ok(loaded.into())
+}
+
+/// Parses the `compression` constructor argument ("zlib-stream",
+/// "zstd-stream", or `None`) into the internal enum `Gateway` uses.
+fn parse_compression(value: Option<String>) -> PyResult<GatewayCompression> {
+    match value.as_deref() {
+        None => Ok(GatewayCompression::None),
+        Some("zlib-stream") => Ok(GatewayCompression::ZlibStream),
+        Some("zstd-stream") => Ok(GatewayCompression::ZstdStream),
+        Some(other) => Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "Unknown compression mode: {:?} (expected \"zlib-stream\" or \"zstd-stream\")",
+            other
+        ))),
+    }
+}
+
+/// `async def _run_safely(coro): try: await coro except Exception: traceback.print_exc()`
+fn build_safe_runner(py: Python) -> PyResult<Py<PyAny>> {
+    let module = pyo3::types::PyModule::from_code_bound(
+        py,
+        "import traceback\n\
+         async def _run_safely(coro):\n\
+         \ttry:\n\
+         \t\tawait coro\n\
+         \texcept Exception:\n\
+         \t\ttraceback.print_exc()\n",
+        "discord_rust_event_runner.py",
+        "discord_rust_event_runner",
+    )?;
+    Ok(module.getattr("_run_safely")?.into())
+}
+
+/// Start a dedicated OS thread running its own asyncio event loop, so
+/// dispatched listener coroutines have a loop that's actually running to
+/// land on via `run_coroutine_threadsafe`, independent of the tokio runtime
+/// driving the gateway.
+fn spawn_event_loop_thread() -> PyResult<(Py<PyAny>, std::thread::JoinHandle<()>)> {
+    let event_loop: Py<PyAny> = Python::with_gil(|py| -> PyResult<Py<PyAny>> {
+        let asyncio = py.import_bound("asyncio")?;
+        Ok(asyncio.call_method0("new_event_loop")?.into())
+    })?;
+
+    let loop_for_thread = Python::with_gil(|py| event_loop.clone_ref(py));
+    let handle = std::thread::Builder::new()
+        .name("discord-event-loop".to_string())
+        .spawn(move || {
+            Python::with_gil(|py| {
+                if let Err(e) = loop_for_thread.call_method0(py, "run_forever") {
+                    eprintln!("event loop thread exited with error: {}", e);
+                }
+            });
+        })
+        .expect("failed to spawn event loop thread");
+
+    Ok((event_loop, handle))
+}
+
+/// Stop the loop started by `spawn_event_loop_thread` and wait for its
+/// thread to exit.
+fn stop_event_loop_thread(event_loop: Py<PyAny>, handle: std::thread::JoinHandle<()>) {
+    Python::with_gil(|py| {
+        if let Ok(stop) = event_loop.getattr(py, "stop") {
+            let _ = event_loop.call_method1(py, "call_soon_threadsafe", (stop,));
+        }
+    });
+    let _ = handle.join();
+}
+
 /// Discord Client
 #[pyclass]
 pub struct Client {
@@ -15,17 +292,30 @@ pub struct Client {
     state: Arc<State>,
     token: String,
     intents: Intents,
+    handlers: EventHandlers,
+    shard_manager: Arc<RwLock<Option<Arc<ShardManager>>>>,
+    gateway_url_override: Option<String>,
+    compression: GatewayCompression,
 }
 
 #[pymethods]
 impl Client {
     #[new]
-    #[pyo3(signature = (*, intents=None))]
-    fn new(intents: Option<Intents>) -> PyResult<Self> {
-        let http = HTTPClient::new()
-            .map_err(|e| PyErr::from(e))?;
+    #[pyo3(signature = (*, intents=None, api_base=None, gateway_url_override=None, compression=None))]
+    fn new(
+        intents: Option<Intents>,
+        api_base: Option<String>,
+        gateway_url_override: Option<String>,
+        compression: Option<String>,
+    ) -> PyResult<Self> {
+        let http = match api_base {
+            Some(api_base) => HTTPClient::with_api_base(api_base),
+            None => HTTPClient::new(),
+        }
+        .map_err(|e| PyErr::from(e))?;
 
         let intents = intents.unwrap_or_else(|| Intents::default());
+        let compression = parse_compression(compression)?;
 
         Ok(Client {
             http: Arc::new(http),
@@ -33,11 +323,124 @@ impl Client {
             state: Arc::new(State::new()),
             token: String::new(),
             intents,
+            handlers: EventHandlers::new(),
+            shard_manager: Arc::new(RwLock::new(None)),
+            gateway_url_override,
+            compression,
         })
     }
 
-    /// Run the bot with the given token
-    fn run(&mut self, token: String) -> PyResult<()> {
+    /// Construct a `Client` targeting a self-hosted or alternate
+    /// Discord-compatible instance (e.g. a Spacebar server), rather than
+    /// discord.com.
+    #[staticmethod]
+    #[pyo3(signature = (*, api_base, gateway_url=None, intents=None, compression=None))]
+    fn from_instance(
+        api_base: String,
+        gateway_url: Option<String>,
+        intents: Option<Intents>,
+        compression: Option<String>,
+    ) -> PyResult<Self> {
+        Self::new(intents, Some(api_base), gateway_url, compression)
+    }
+
+    /// Register `coro` as a listener for the event named by its own
+    /// `__name__` (e.g. a function named `on_message` listens for
+    /// `MESSAGE_CREATE`). Mirrors discord.py's `@client.event` decorator.
+    fn event(&self, py: Python, coro: Py<PyAny>) -> PyResult<Py<PyAny>> {
+        let name: String = coro.getattr(py, "__name__")?.extract(py)?;
+        self.handlers.add(name, coro.clone_ref(py));
+        Ok(coro)
+    }
+
+    /// Register `coro` as a listener for `name` without replacing any
+    /// existing listeners for that event.
+    #[pyo3(signature = (name, coro))]
+    fn add_listener(&self, name: String, coro: Py<PyAny>) -> PyResult<()> {
+        self.handlers.add(name, coro);
+        Ok(())
+    }
+
+    /// Remove a previously registered listener for `name`.
+    #[pyo3(signature = (name, coro))]
+    fn remove_listener(&self, py: Python, name: String, coro: Py<PyAny>) -> PyResult<()> {
+        self.handlers.remove(py, &name, &coro);
+        Ok(())
+    }
+
+    /// Register a listener for the raw gateway event `name` (e.g.
+    /// `"MESSAGE_CREATE"`), bypassing the `on_xxx` mapping `event` and
+    /// `add_listener` use. Meant to be used as a decorator:
+    /// `@client.on("MESSAGE_CREATE")`.
+    fn on<'py>(&self, py: Python<'py>, name: String) -> PyResult<Bound<'py, PyCFunction>> {
+        let handlers = self.handlers.clone();
+        PyCFunction::new_closure_bound(
+            py,
+            None,
+            None,
+            move |args: &Bound<'_, PyTuple>, _kwargs| -> PyResult<Py<PyAny>> {
+                let coro: Py<PyAny> = args.get_item(0)?.extract()?;
+                handlers.add_raw(name.clone(), coro.clone_ref(args.py()));
+                Ok(coro)
+            },
+        )
+    }
+
+    /// Look up a cached guild by id. Returns `None` if the guild hasn't
+    /// been seen via READY/`GUILD_CREATE` yet.
+    fn get_guild(&self, py: Python, id: Snowflake) -> PyResult<Option<Py<PyAny>>> {
+        self.state
+            .get_guild(id)
+            .map(|data| json_to_py(py, &data))
+            .transpose()
+    }
+
+    /// Look up a cached channel by id.
+    fn get_channel(&self, py: Python, id: Snowflake) -> PyResult<Option<Py<PyAny>>> {
+        self.state
+            .get_channel(id)
+            .map(|data| json_to_py(py, &data))
+            .transpose()
+    }
+
+    /// Look up a cached user by id. Populated from users seen on the bot's
+    /// own READY payload and member events, not a full user directory.
+    fn get_user(&self, py: Python, id: Snowflake) -> PyResult<Option<Py<PyAny>>> {
+        self.state
+            .get_user(id)
+            .map(|data| json_to_py(py, &data))
+            .transpose()
+    }
+
+    /// Look up a cached guild member by guild and user id.
+    fn get_member(
+        &self,
+        py: Python,
+        guild_id: Snowflake,
+        user_id: Snowflake,
+    ) -> PyResult<Option<Py<PyAny>>> {
+        self.state
+            .get_member(guild_id, user_id)
+            .map(|data| json_to_py(py, &data))
+            .transpose()
+    }
+
+    /// Run the bot with the given token.
+    ///
+    /// By default the recommended shard count from `/gateway/bot` is used.
+    /// Pass `shard_count` to override it, or `shard_ids` to only run a
+    /// subset of shards in this process (e.g. when spreading shards across
+    /// multiple machines). Set `reconnect=False` to let a dropped
+    /// connection propagate instead of being retried with backoff.
+    #[pyo3(signature = (token, *, shard_count=None, shard_ids=None, reconnect=true))]
+    fn run(
+        &mut self,
+        py: Python,
+        token: String,
+        shard_count: Option<u32>,
+        shard_ids: Option<Vec<u32>>,
+        reconnect: bool,
+    ) -> PyResult<()> {
         self.token = token.clone();
         let http = Arc::clone(&self.http);
 
@@ -45,14 +448,67 @@ impl Client {
         let token_clone = token.clone();
         let intents = self.intents;
 
+        let (event_loop, loop_thread) = spawn_event_loop_thread()?;
+        self.handlers
+            .set_event_loop(Python::with_gil(|py| event_loop.clone_ref(py)));
+
         // Run the async runtime in a blocking context
-        tokio::runtime::Runtime::new()
+        // The GIL is released while blocking so the event loop thread and
+        // the tokio workers dispatching events can acquire it.
+        let result = py.allow_threads(|| tokio::runtime::Runtime::new()
             .unwrap()
             .block_on(async move {
                 http.set_token(token_clone.clone()).await;
-                client.connect(token_clone, intents).await
+                client
+                    .connect(token_clone, intents, shard_count, shard_ids, reconnect)
+                    .await
                     .map_err(|e| PyErr::from(e))
-            })
+            }));
+
+        py.allow_threads(|| stop_event_loop_thread(event_loop, loop_thread));
+        result
+    }
+
+    /// Query `/gateway/bot` for the shard count Discord recommends for this
+    /// token, without starting a connection. Useful for splitting shards
+    /// across multiple processes before calling `run` with `shard_ids`.
+    fn recommended_shards<'py>(
+        &self,
+        py: Python<'py>,
+        token: String,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let http = Arc::clone(&self.http);
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            http.set_token(token).await;
+            let bot_info = http.get_gateway_bot().await.map_err(PyErr::from)?;
+            Ok(bot_info["shards"].as_u64().unwrap_or(1) as u32)
+        })
+    }
+
+    /// Per-shard connection status, keyed by shard id. Empty until `run`
+    /// has started and `/gateway/bot` has been consulted.
+    #[getter]
+    fn shards(&self, py: Python) -> PyResult<Py<pyo3::types::PyDict>> {
+        let dict = pyo3::types::PyDict::new_bound(py);
+        if let Ok(manager) = self.shard_manager.try_read() {
+            if let Some(manager) = manager.as_ref() {
+                for entry in manager.statuses().iter() {
+                    dict.set_item(*entry.key(), entry.value().clone().into_py(py))?;
+                }
+            }
+        }
+        Ok(dict.into())
+    }
+
+    /// The bot's own user object, cached from READY. `None` until the
+    /// gateway connection has completed its handshake.
+    #[getter]
+    fn user(&self, py: Python) -> PyResult<Option<Py<PyAny>>> {
+        self.state
+            .get_user_id()
+            .and_then(|id| self.state.get_user(id))
+            .map(|data| json_to_py(py, &data))
+            .transpose()
     }
 
     fn __repr__(&self) -> String {
@@ -65,6 +521,10 @@ impl Client {
         ClientInternal {
             http: Arc::clone(&self.http),
             state: Arc::clone(&self.state),
+            handlers: self.handlers.clone(),
+            shard_manager: Arc::clone(&self.shard_manager),
+            gateway_url_override: self.gateway_url_override.clone(),
+            compression: self.compression,
         }
     }
 }
@@ -72,37 +532,46 @@ impl Client {
 struct ClientInternal {
     http: Arc<HTTPClient>,
     state: Arc<State>,
+    handlers: EventHandlers,
+    shard_manager: Arc<RwLock<Option<Arc<ShardManager>>>>,
+    gateway_url_override: Option<String>,
+    compression: GatewayCompression,
 }
 
 impl ClientInternal {
-    async fn connect(&self, token: String, intents: Intents) -> Result<(), DiscordError> {
-        // Get gateway URL
-        let gateway_url = self.http.get_gateway().await?;
-
-        // Create gateway connection
-        let gateway = Gateway::new(token, intents);
-        gateway.connect(&gateway_url).await?;
-
-        // Start receiving events
-        loop {
-            if let Some(payload) = gateway.receive().await? {
-                gateway.handle_payload(&payload).await?;
-
-                // Start heartbeat after receiving HELLO
-                if payload["op"].as_u64() == Some(10) {
-                    gateway.start_heartbeat().await;
-                }
+    async fn connect(
+        &self,
+        token: String,
+        intents: Intents,
+        shard_count: Option<u32>,
+        shard_ids: Option<Vec<u32>>,
+        reconnect: bool,
+    ) -> Result<(), DiscordError> {
+        let (manager, gateway_url) = ShardManager::from_gateway_bot(
+            &self.http,
+            shard_count,
+            shard_ids,
+            self.gateway_url_override.clone(),
+            self.compression,
+        )
+        .await?;
+        let manager = Arc::new(manager);
+        *self.shard_manager.write().await = Some(Arc::clone(&manager));
 
-                // Handle events
-                if payload["op"].as_u64() == Some(0) {
-                    // Dispatch event
-                    if let Some(event_type) = payload["t"].as_str() {
-                        println!("Received event: {}", event_type);
-                        // Event handlers will go here
-                    }
-                }
-            }
-        }
+        let handlers = self.handlers.clone();
+        let state = Arc::clone(&self.state);
+        manager
+            .run(
+                token,
+                intents,
+                gateway_url,
+                reconnect,
+                move |shard_id, event_type, data| {
+                    state.apply_dispatch(event_type, data);
+                    handlers.dispatch_with_shard(shard_id, event_type, data);
+                },
+            )
+            .await
     }
 }
 
@@ -1,26 +1,71 @@
-use pyo3::prelude::*;
-use pyo3::exceptions::PyException;
 use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
 
 // Base Discord exception
-create_exception!(discord, DiscordException, PyException, "Base exception class for discord.py");
+create_exception!(
+    discord,
+    DiscordException,
+    PyException,
+    "Base exception class for discord.py"
+);
 
 // Client exceptions
-create_exception!(discord, ClientException, DiscordException, "Exception that's raised when an operation in the Client fails.");
-create_exception!(discord, GatewayNotFound, DiscordException, "An exception that is raised when the gateway for Discord could not be found");
-create_exception!(discord, InvalidData, ClientException, "Exception that's raised when the library encounters unknown or invalid data from Discord.");
+create_exception!(
+    discord,
+    ClientException,
+    DiscordException,
+    "Exception that's raised when an operation in the Client fails."
+);
+create_exception!(
+    discord,
+    GatewayNotFound,
+    DiscordException,
+    "An exception that is raised when the gateway for Discord could not be found"
+);
+create_exception!(
+    discord,
+    InvalidData,
+    ClientException,
+    "Exception that's raised when the library encounters unknown or invalid data from Discord."
+);
 create_exception!(discord, LoginFailure, ClientException, "Exception that's raised when the Client.login function fails to log you in from improper credentials or some other misc. failure.");
 create_exception!(discord, ConnectionClosed, ClientException, "Exception that's raised when the gateway connection is closed for reasons that could not be handled internally.");
 create_exception!(discord, PrivilegedIntentsRequired, ClientException, "Exception that's raised when the gateway is requesting privileged intents but they're not ticked in the developer page yet.");
 create_exception!(discord, InteractionResponded, ClientException, "Exception that's raised when sending another interaction response using InteractionResponse when one has already been done before.");
-create_exception!(discord, MissingApplicationID, ClientException, "An exception raised when the client does not have an application ID set.");
+create_exception!(
+    discord,
+    MissingApplicationID,
+    ClientException,
+    "An exception raised when the client does not have an application ID set."
+);
 
 // HTTP exceptions
-create_exception!(discord, HTTPException, DiscordException, "Exception that's raised when an HTTP request operation fails.");
+create_exception!(
+    discord,
+    HTTPException,
+    DiscordException,
+    "Exception that's raised when an HTTP request operation fails."
+);
 create_exception!(discord, RateLimited, DiscordException, "Exception that's raised for when status code 429 occurs and the timeout is greater than the configured maximum.");
-create_exception!(discord, Forbidden, HTTPException, "Exception that's raised for when status code 403 occurs.");
-create_exception!(discord, NotFound, HTTPException, "Exception that's raised for when status code 404 occurs.");
-create_exception!(discord, DiscordServerError, HTTPException, "Exception that's raised for when a 500 range status code occurs.");
+create_exception!(
+    discord,
+    Forbidden,
+    HTTPException,
+    "Exception that's raised for when status code 403 occurs."
+);
+create_exception!(
+    discord,
+    NotFound,
+    HTTPException,
+    "Exception that's raised for when status code 404 occurs."
+);
+create_exception!(
+    discord,
+    DiscordServerError,
+    HTTPException,
+    "Exception that's raised for when a 500 range status code occurs."
+);
 
 /// Custom error type used internally
 #[derive(Debug)]
@@ -28,13 +73,50 @@ pub enum DiscordError {
     Http(reqwest::Error),
     WebSocket(tokio_tungstenite::tungstenite::Error),
     Json(serde_json::Error),
+    Io(std::io::Error),
     Gateway(String),
     InvalidData(String),
-    ConnectionClosed { code: i32, reason: String },
-    RateLimited { retry_after: f64 },
+    ConnectionClosed {
+        code: i32,
+        reason: String,
+    },
+    RateLimited {
+        retry_after: f64,
+    },
     Forbidden,
     NotFound,
     ServerError,
+    /// Gateway close 4014: the bot requires a privileged intent that
+    /// hasn't been enabled in the developer portal. Not recoverable by
+    /// retrying.
+    PrivilegedIntentsRequired,
+    /// Gateway close 4004: the token was rejected. Not recoverable by
+    /// retrying.
+    LoginFailure(String),
+    /// Gateway close 4010: the `shard` IDENTIFY field was invalid.
+    InvalidShard,
+    /// Gateway close 4011: the guild count requires more shards than were
+    /// started.
+    ShardingRequired,
+    /// Gateway close 4013: one or more intent values in IDENTIFY are
+    /// invalid (as opposed to 4014, which means they're valid but not
+    /// enabled for the application).
+    DisallowedIntents,
+}
+
+impl DiscordError {
+    /// Whether a reconnect supervisor should retry after this error, as
+    /// opposed to giving up and propagating it to the caller.
+    pub fn is_recoverable(&self) -> bool {
+        !matches!(
+            self,
+            DiscordError::PrivilegedIntentsRequired
+                | DiscordError::LoginFailure(_)
+                | DiscordError::InvalidShard
+                | DiscordError::ShardingRequired
+                | DiscordError::DisallowedIntents
+        )
+    }
 }
 
 impl std::fmt::Display for DiscordError {
@@ -43,6 +125,7 @@ impl std::fmt::Display for DiscordError {
             DiscordError::Http(e) => write!(f, "HTTP error: {}", e),
             DiscordError::WebSocket(e) => write!(f, "WebSocket error: {}", e),
             DiscordError::Json(e) => write!(f, "JSON error: {}", e),
+            DiscordError::Io(e) => write!(f, "IO error: {}", e),
             DiscordError::Gateway(s) => write!(f, "Gateway error: {}", s),
             DiscordError::InvalidData(s) => write!(f, "Invalid data: {}", s),
             DiscordError::ConnectionClosed { code, reason } => {
@@ -54,6 +137,16 @@ impl std::fmt::Display for DiscordError {
             DiscordError::Forbidden => write!(f, "Forbidden (403)"),
             DiscordError::NotFound => write!(f, "Not found (404)"),
             DiscordError::ServerError => write!(f, "Discord server error (5xx)"),
+            DiscordError::PrivilegedIntentsRequired => {
+                write!(
+                    f,
+                    "Privileged intents are required but not enabled for this application"
+                )
+            }
+            DiscordError::LoginFailure(reason) => write!(f, "Login failure: {}", reason),
+            DiscordError::InvalidShard => write!(f, "Invalid shard"),
+            DiscordError::ShardingRequired => write!(f, "Sharding is required for this bot"),
+            DiscordError::DisallowedIntents => write!(f, "One or more intents are invalid"),
         }
     }
 }
@@ -78,23 +171,44 @@ impl From<serde_json::Error> for DiscordError {
     }
 }
 
+impl From<std::io::Error> for DiscordError {
+    fn from(e: std::io::Error) -> Self {
+        DiscordError::Io(e)
+    }
+}
+
 impl From<DiscordError> for PyErr {
     fn from(err: DiscordError) -> PyErr {
         match err {
             DiscordError::Http(e) => HTTPException::new_err(format!("HTTP error: {}", e)),
-            DiscordError::WebSocket(e) => ConnectionClosed::new_err(format!("WebSocket error: {}", e)),
+            DiscordError::WebSocket(e) => {
+                ConnectionClosed::new_err(format!("WebSocket error: {}", e))
+            }
             DiscordError::Json(e) => InvalidData::new_err(format!("JSON error: {}", e)),
+            DiscordError::Io(e) => ClientException::new_err(format!("IO error: {}", e)),
             DiscordError::Gateway(s) => GatewayNotFound::new_err(s),
             DiscordError::InvalidData(s) => InvalidData::new_err(s),
-            DiscordError::ConnectionClosed { code, reason } => {
-                ConnectionClosed::new_err(format!("Connection closed with code {}: {}", code, reason))
-            }
+            DiscordError::ConnectionClosed { code, reason } => ConnectionClosed::new_err(format!(
+                "Connection closed with code {}: {}",
+                code, reason
+            )),
             DiscordError::RateLimited { retry_after } => {
                 RateLimited::new_err(format!("Rate limited, retry after {} seconds", retry_after))
             }
             DiscordError::Forbidden => Forbidden::new_err("Forbidden (403)"),
             DiscordError::NotFound => NotFound::new_err("Not found (404)"),
             DiscordError::ServerError => DiscordServerError::new_err("Discord server error (5xx)"),
+            DiscordError::PrivilegedIntentsRequired => PrivilegedIntentsRequired::new_err(
+                "Privileged intents are required but not enabled for this application",
+            ),
+            DiscordError::LoginFailure(reason) => LoginFailure::new_err(reason),
+            DiscordError::InvalidShard => ClientException::new_err("Invalid shard"),
+            DiscordError::ShardingRequired => {
+                ClientException::new_err("Sharding is required for this bot")
+            }
+            DiscordError::DisallowedIntents => {
+                ClientException::new_err("One or more intents are invalid")
+            }
         }
     }
 }
@@ -108,12 +222,24 @@ pub fn register_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add("RateLimited", py.get_type_bound::<RateLimited>())?;
     m.add("Forbidden", py.get_type_bound::<Forbidden>())?;
     m.add("NotFound", py.get_type_bound::<NotFound>())?;
-    m.add("DiscordServerError", py.get_type_bound::<DiscordServerError>())?;
+    m.add(
+        "DiscordServerError",
+        py.get_type_bound::<DiscordServerError>(),
+    )?;
     m.add("InvalidData", py.get_type_bound::<InvalidData>())?;
     m.add("LoginFailure", py.get_type_bound::<LoginFailure>())?;
     m.add("ConnectionClosed", py.get_type_bound::<ConnectionClosed>())?;
-    m.add("PrivilegedIntentsRequired", py.get_type_bound::<PrivilegedIntentsRequired>())?;
-    m.add("InteractionResponded", py.get_type_bound::<InteractionResponded>())?;
-    m.add("MissingApplicationID", py.get_type_bound::<MissingApplicationID>())?;
+    m.add(
+        "PrivilegedIntentsRequired",
+        py.get_type_bound::<PrivilegedIntentsRequired>(),
+    )?;
+    m.add(
+        "InteractionResponded",
+        py.get_type_bound::<InteractionResponded>(),
+    )?;
+    m.add(
+        "MissingApplicationID",
+        py.get_type_bound::<MissingApplicationID>(),
+    )?;
     Ok(())
 }
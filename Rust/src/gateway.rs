@@ -1,19 +1,79 @@
+use crate::enums::Intents;
+use crate::errors::DiscordError;
+use flate2::{Decompress, FlushDecompress};
+use futures::{SinkExt, StreamExt};
 use pyo3::prelude::*;
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
 use tokio::net::TcpStream;
+use tokio::sync::RwLock;
+use tokio::time::{interval, sleep, Duration};
 use tokio_tungstenite::{
     connect_async, tungstenite::protocol::Message, MaybeTlsStream, WebSocketStream,
 };
-use futures::{SinkExt, StreamExt};
-use serde_json::{json, Value};
-use std::sync::Arc;
-use tokio::sync::RwLock;
-use tokio::time::{sleep, Duration, interval};
-use crate::errors::DiscordError;
-use crate::enums::Intents;
+use zstd::stream::raw::{Decoder as ZstdRawDecoder, InBuffer, Operation, OutBuffer};
 
 const GATEWAY_VERSION: u8 = 10;
 const GATEWAY_ENCODING: &str = "json";
 
+/// The 4-byte suffix zlib-stream's Z_SYNC_FLUSH appends to mark "this is
+/// everything Discord has sent so far" — a frame is only complete once we
+/// see it.
+const ZLIB_SYNC_FLUSH_SUFFIX: [u8; 4] = [0x00, 0x00, 0xFF, 0xFF];
+
+/// Transport compression negotiated via the gateway connect URL's
+/// `compress` query parameter. Trades CPU (for decompression) for
+/// bandwidth; `None` sends and receives plain JSON text frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GatewayCompression {
+    #[default]
+    None,
+    ZlibStream,
+    ZstdStream,
+}
+
+impl GatewayCompression {
+    fn query_param(self) -> Option<&'static str> {
+        match self {
+            GatewayCompression::None => None,
+            GatewayCompression::ZlibStream => Some("zlib-stream"),
+            GatewayCompression::ZstdStream => Some("zstd-stream"),
+        }
+    }
+}
+
+/// Per-connection decompression state. Both zlib-stream and zstd-stream are
+/// one continuous compressed stream for the life of the connection, not one
+/// independently-compressed message per frame, so the decoder (and any
+/// partial-frame bytes) must persist across `receive` calls.
+enum DecompressState {
+    None,
+    Zlib {
+        inflater: Decompress,
+        buffer: Vec<u8>,
+    },
+    Zstd {
+        decoder: ZstdRawDecoder<'static>,
+        buffer: Vec<u8>,
+    },
+}
+
+impl DecompressState {
+    fn new(compression: GatewayCompression) -> Self {
+        match compression {
+            GatewayCompression::None => DecompressState::None,
+            GatewayCompression::ZlibStream => DecompressState::Zlib {
+                inflater: Decompress::new(true),
+                buffer: Vec::new(),
+            },
+            GatewayCompression::ZstdStream => DecompressState::Zstd {
+                decoder: ZstdRawDecoder::new().expect("failed to initialize zstd decoder"),
+                buffer: Vec::new(),
+            },
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum GatewayOpcode {
     Dispatch = 0,
@@ -48,38 +108,118 @@ impl GatewayOpcode {
     }
 }
 
+/// Action the caller should take in response to the most recent payload,
+/// so reconnection policy can live in the caller (`ShardManager`) rather
+/// than here.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GatewayAction {
+    /// Nothing special needed; keep receiving.
+    None,
+    /// The session is resumable: reconnect (to `resume_gateway_url` if set)
+    /// and send RESUME instead of IDENTIFY.
+    Resume,
+    /// The session is not resumable: reconnect and send a fresh IDENTIFY.
+    ReIdentify,
+}
+
 pub struct Gateway {
     ws: Arc<RwLock<Option<WebSocketStream<MaybeTlsStream<TcpStream>>>>>,
     token: String,
     intents: Intents,
+    /// `[shard_id, shard_count]` sent with IDENTIFY, if this gateway belongs
+    /// to a sharded connection.
+    shard: Option<(u32, u32)>,
+    compression: GatewayCompression,
+    decompress: Arc<Mutex<DecompressState>>,
     sequence: Arc<RwLock<Option<u64>>>,
     session_id: Arc<RwLock<Option<String>>>,
+    resume_gateway_url: Arc<RwLock<Option<String>>>,
     heartbeat_interval: Arc<RwLock<Option<u64>>>,
     last_heartbeat_ack: Arc<RwLock<bool>>,
+    /// Handle of the currently running heartbeat loop, if any. Aborted and
+    /// replaced each time `start_heartbeat` runs so a reconnect can't leave
+    /// a previous loop beating against the freshly-swapped socket.
+    heartbeat_task: Arc<tokio::sync::Mutex<Option<tokio::task::JoinHandle<()>>>>,
+    /// Whether the connection currently being (re)established should
+    /// RESUME rather than IDENTIFY once HELLO arrives. Set by the caller
+    /// before `connect`, so HELLO only ever triggers one or the other.
+    resuming: Arc<RwLock<bool>>,
 }
 
 impl Gateway {
-    pub fn new(token: String, intents: Intents) -> Self {
+    pub fn new(token: String, intents: Intents, compression: GatewayCompression) -> Self {
         Gateway {
             ws: Arc::new(RwLock::new(None)),
             token,
             intents,
+            shard: None,
+            compression,
+            decompress: Arc::new(Mutex::new(DecompressState::new(compression))),
             sequence: Arc::new(RwLock::new(None)),
             session_id: Arc::new(RwLock::new(None)),
+            resume_gateway_url: Arc::new(RwLock::new(None)),
             heartbeat_interval: Arc::new(RwLock::new(None)),
             last_heartbeat_ack: Arc::new(RwLock::new(true)),
+            heartbeat_task: Arc::new(tokio::sync::Mutex::new(None)),
+            resuming: Arc::new(RwLock::new(false)),
         }
     }
 
+    /// Like `new`, but IDENTIFYs with a `shard: [shard_id, shard_count]`
+    /// array so Discord routes only this shard's guilds to the connection.
+    pub fn new_sharded(
+        token: String,
+        intents: Intents,
+        shard_id: u32,
+        shard_count: u32,
+        compression: GatewayCompression,
+    ) -> Self {
+        let mut gateway = Self::new(token, intents, compression);
+        gateway.shard = Some((shard_id, shard_count));
+        gateway
+    }
+
     pub async fn connect(&self, gateway_url: &str) -> Result<(), DiscordError> {
-        let url = format!("{}/?v={}&encoding={}", gateway_url, GATEWAY_VERSION, GATEWAY_ENCODING);
+        let mut url = format!(
+            "{}/?v={}&encoding={}",
+            gateway_url, GATEWAY_VERSION, GATEWAY_ENCODING
+        );
+        if let Some(compress) = self.compression.query_param() {
+            url.push_str("&compress=");
+            url.push_str(compress);
+        }
         let (ws_stream, _) = connect_async(url).await?;
         *self.ws.write().await = Some(ws_stream);
+        *self.decompress.lock().unwrap() = DecompressState::new(self.compression);
         Ok(())
     }
 
+    /// The session id of the most recent READY, if any (used to decide
+    /// whether resuming is even possible).
+    pub async fn session_id(&self) -> Option<String> {
+        self.session_id.read().await.clone()
+    }
+
+    /// The last sequence number observed on any payload.
+    pub async fn sequence(&self) -> Option<u64> {
+        *self.sequence.read().await
+    }
+
+    /// The gateway URL Discord told us to reconnect to for a RESUME,
+    /// distinct from the general `/gateway` URL.
+    pub async fn resume_gateway_url(&self) -> Option<String> {
+        self.resume_gateway_url.read().await.clone()
+    }
+
+    /// Mark whether the connection about to be (re)established should
+    /// RESUME once HELLO arrives, instead of IDENTIFY. Must be called
+    /// before `connect` for the upcoming socket.
+    pub async fn set_resuming(&self, resuming: bool) {
+        *self.resuming.write().await = resuming;
+    }
+
     pub async fn send_identify(&self) -> Result<(), DiscordError> {
-        let identify = json!({
+        let mut identify = json!({
             "op": GatewayOpcode::Identify as u8,
             "d": {
                 "token": self.token,
@@ -92,18 +232,32 @@ impl Gateway {
             }
         });
 
+        if let Some((shard_id, shard_count)) = self.shard {
+            identify["d"]["shard"] = json!([shard_id, shard_count]);
+        }
+
         self.send_json(&identify).await
     }
 
-    pub async fn send_heartbeat(&self) -> Result<(), DiscordError> {
+    /// Send a RESUME (op 6) using the stored session id and last sequence,
+    /// so Discord replays any dispatches we missed while disconnected
+    /// instead of starting a brand new session.
+    pub async fn send_resume(&self) -> Result<(), DiscordError> {
+        let session_id = self.session_id.read().await.clone().ok_or_else(|| {
+            DiscordError::Gateway("Cannot resume: no session_id stored".to_string())
+        })?;
         let seq = *self.sequence.read().await;
-        let heartbeat = json!({
-            "op": GatewayOpcode::Heartbeat as u8,
-            "d": seq
+
+        let resume = json!({
+            "op": GatewayOpcode::Resume as u8,
+            "d": {
+                "token": self.token,
+                "session_id": session_id,
+                "seq": seq,
+            }
         });
 
-        *self.last_heartbeat_ack.write().await = false;
-        self.send_json(&heartbeat).await
+        self.send_json(&resume).await
     }
 
     async fn send_json(&self, data: &Value) -> Result<(), DiscordError> {
@@ -115,6 +269,103 @@ impl Gateway {
         Ok(())
     }
 
+    /// Feed one binary websocket frame through the connection's persistent
+    /// decompressor, returning the decoded JSON text once a full message
+    /// has arrived (or `None` while a frame is still accumulating).
+    fn decompress_frame(&self, bin: &[u8]) -> Result<Option<String>, DiscordError> {
+        match self.compression {
+            GatewayCompression::None => Ok(Some(String::from_utf8_lossy(bin).into_owned())),
+            GatewayCompression::ZlibStream => self.decompress_zlib_frame(bin),
+            GatewayCompression::ZstdStream => self.decompress_zstd_frame(bin),
+        }
+    }
+
+    fn decompress_zlib_frame(&self, bin: &[u8]) -> Result<Option<String>, DiscordError> {
+        let mut state = self.decompress.lock().unwrap();
+        let (inflater, buffer) = match &mut *state {
+            DecompressState::Zlib { inflater, buffer } => (inflater, buffer),
+            _ => {
+                return Err(DiscordError::Gateway(
+                    "Zlib decompressor not initialized".to_string(),
+                ))
+            }
+        };
+
+        buffer.extend_from_slice(bin);
+
+        if !buffer.ends_with(&ZLIB_SYNC_FLUSH_SUFFIX) {
+            return Ok(None);
+        }
+
+        let mut output = Vec::new();
+        let mut chunk = [0u8; 8192];
+        let mut consumed = 0usize;
+
+        while consumed < buffer.len() {
+            let before_in = inflater.total_in();
+            let before_out = inflater.total_out();
+            inflater
+                .decompress(&buffer[consumed..], &mut chunk, FlushDecompress::Sync)
+                .map_err(|e| DiscordError::Gateway(format!("Zlib decompress failed: {}", e)))?;
+            consumed += (inflater.total_in() - before_in) as usize;
+            let produced = (inflater.total_out() - before_out) as usize;
+            output.extend_from_slice(&chunk[..produced]);
+            if produced == 0 {
+                break;
+            }
+        }
+
+        buffer.clear();
+        String::from_utf8(output).map(Some).map_err(|e| {
+            DiscordError::InvalidData(format!("Invalid UTF-8 in decompressed payload: {}", e))
+        })
+    }
+
+    fn decompress_zstd_frame(&self, bin: &[u8]) -> Result<Option<String>, DiscordError> {
+        let mut state = self.decompress.lock().unwrap();
+        let (decoder, buffer) = match &mut *state {
+            DecompressState::Zstd { decoder, buffer } => (decoder, buffer),
+            _ => {
+                return Err(DiscordError::Gateway(
+                    "Zstd decompressor not initialized".to_string(),
+                ))
+            }
+        };
+
+        buffer.extend_from_slice(bin);
+
+        let mut input = InBuffer::around(&buffer[..]);
+        let mut output = vec![0u8; 8192];
+        let mut decoded = Vec::new();
+
+        while input.pos < input.src.len() {
+            let mut out_buf = OutBuffer::around(&mut output);
+            decoder
+                .run(&mut input, &mut out_buf)
+                .map_err(|e| DiscordError::Gateway(format!("Zstd decompress failed: {}", e)))?;
+            let produced = out_buf.as_slice().len();
+            decoded.extend_from_slice(out_buf.as_slice());
+            if produced == 0 {
+                break;
+            }
+        }
+
+        let fully_consumed = input.pos == input.src.len();
+        buffer.drain(0..input.pos);
+
+        // Discord keeps a single zstd frame open for the whole connection
+        // and flushes per-message, so the frame never completes (`run`'s
+        // hint never reaches 0) mid-stream. A message is done once we've
+        // consumed everything buffered and produced some output.
+        if fully_consumed && !decoded.is_empty() {
+            String::from_utf8(decoded).map(Some).map_err(|e| {
+                DiscordError::InvalidData(format!("Invalid UTF-8 in decompressed payload: {}", e))
+            })
+        } else {
+            Ok(None)
+        }
+    }
+
     pub async fn receive(&self) -> Result<Option<Value>, DiscordError> {
         let mut ws_guard = self.ws.write().await;
         if let Some(ws) = &mut *ws_guard {
@@ -125,31 +376,57 @@ impl Gateway {
                         let data: Value = serde_json::from_str(&text)?;
                         Ok(Some(data))
                     }
-                    Message::Binary(bin) => {
-                        // Handle zlib-compressed payloads if needed
-                        let text = String::from_utf8_lossy(&bin);
-                        let data: Value = serde_json::from_str(&text)?;
-                        Ok(Some(data))
-                    }
+                    Message::Binary(bin) => match self.decompress_frame(&bin)? {
+                        Some(text) => {
+                            let data: Value = serde_json::from_str(&text)?;
+                            Ok(Some(data))
+                        }
+                        // Partial frame: Discord hasn't flushed the rest of
+                        // this message yet, so there's nothing to parse.
+                        None => Ok(None),
+                    },
                     Message::Close(frame) => {
-                        let code = frame.as_ref().map(|f| f.code.into()).unwrap_or(1000);
-                        let reason = frame.as_ref().map(|f| f.reason.to_string()).unwrap_or_default();
-                        Err(DiscordError::ConnectionClosed {
-                            code: code as i32,
-                            reason,
+                        let code: u16 = frame.as_ref().map(|f| f.code.into()).unwrap_or(1000);
+                        let reason = frame
+                            .as_ref()
+                            .map(|f| f.reason.to_string())
+                            .unwrap_or_default();
+                        Err(match code {
+                            // Fatal: retrying won't help without the caller
+                            // changing something first.
+                            4004 => DiscordError::LoginFailure(reason),
+                            4010 => DiscordError::InvalidShard,
+                            4011 => DiscordError::ShardingRequired,
+                            4013 => DiscordError::DisallowedIntents,
+                            4014 => DiscordError::PrivilegedIntentsRequired,
+                            // Resumable: 4000/4001/4002/4005/4007/4008/4009
+                            // and a plain 1006 all leave the session intact
+                            // on Discord's side, so fall through to the
+                            // generic (recoverable) close here.
+                            _ => DiscordError::ConnectionClosed {
+                                code: code as i32,
+                                reason,
+                            },
                         })
                     }
                     _ => Ok(None),
                 }
             } else {
-                Ok(None)
+                // The stream ended without a Close frame (e.g. the
+                // connection dropped/timed out). A fused stream keeps
+                // yielding `None` forever, so surface this as a recoverable
+                // disconnect instead of returning `Ok(None)` and spinning.
+                Err(DiscordError::ConnectionClosed {
+                    code: 1006,
+                    reason: "stream ended without a close frame".to_string(),
+                })
             }
         } else {
             Ok(None)
         }
     }
 
-    pub async fn handle_payload(&self, payload: &Value) -> Result<(), DiscordError> {
+    pub async fn handle_payload(&self, payload: &Value) -> Result<GatewayAction, DiscordError> {
         let op = payload["op"].as_u64().unwrap_or(0);
         let opcode = GatewayOpcode::from_u64(op);
 
@@ -157,11 +434,20 @@ impl Gateway {
             *self.sequence.write().await = Some(seq);
         }
 
+        let mut action = GatewayAction::None;
+
         match opcode {
             Some(GatewayOpcode::Hello) => {
-                let heartbeat_interval = payload["d"]["heartbeat_interval"].as_u64().unwrap_or(41250);
+                let heartbeat_interval =
+                    payload["d"]["heartbeat_interval"].as_u64().unwrap_or(41250);
                 *self.heartbeat_interval.write().await = Some(heartbeat_interval);
-                self.send_identify().await?;
+                // A connection intending to RESUME must not also IDENTIFY,
+                // or Discord starts a brand new session instead.
+                if *self.resuming.read().await {
+                    self.send_resume().await?;
+                } else {
+                    self.send_identify().await?;
+                }
             }
             Some(GatewayOpcode::HeartbeatAck) => {
                 *self.last_heartbeat_ack.write().await = true;
@@ -172,19 +458,30 @@ impl Gateway {
                         if let Some(session_id) = payload["d"]["session_id"].as_str() {
                             *self.session_id.write().await = Some(session_id.to_string());
                         }
+                        if let Some(url) = payload["d"]["resume_gateway_url"].as_str() {
+                            *self.resume_gateway_url.write().await = Some(url.to_string());
+                        }
                     }
                 }
             }
             Some(GatewayOpcode::Reconnect) => {
-                // Handle reconnect
+                // Discord is asking us to reconnect; the current session is
+                // still resumable.
+                action = GatewayAction::Resume;
             }
             Some(GatewayOpcode::InvalidSession) => {
-                // Handle invalid session
+                // `d` tells us whether the session can still be resumed.
+                let resumable = payload["d"].as_bool().unwrap_or(false);
+                action = if resumable && self.session_id.read().await.is_some() {
+                    GatewayAction::Resume
+                } else {
+                    GatewayAction::ReIdentify
+                };
             }
             _ => {}
         }
 
-        Ok(())
+        Ok(action)
     }
 
     pub async fn start_heartbeat(&self) {
@@ -193,7 +490,16 @@ impl Gateway {
             let last_heartbeat_ack = Arc::clone(&self.last_heartbeat_ack);
             let ws = Arc::clone(&self.ws);
 
-            tokio::spawn(async move {
+            // HELLO fires on every (re)connect; abort whatever loop was
+            // beating against the previous socket before starting the one
+            // for this one, or reconnects accumulate a heartbeat per
+            // attempt, all racing on `last_heartbeat_ack`.
+            let mut task_guard = self.heartbeat_task.lock().await;
+            if let Some(previous) = task_guard.take() {
+                previous.abort();
+            }
+
+            let handle = tokio::spawn(async move {
                 let mut interval_timer = interval(Duration::from_millis(interval_ms));
                 loop {
                     interval_timer.tick().await;
@@ -226,18 +532,8 @@ impl Gateway {
                     }
                 }
             });
-        }
-    }
 
-    fn clone_for_heartbeat(&self) -> Self {
-        Gateway {
-            ws: Arc::clone(&self.ws),
-            token: self.token.clone(),
-            intents: self.intents,
-            sequence: Arc::clone(&self.sequence),
-            session_id: Arc::clone(&self.session_id),
-            heartbeat_interval: Arc::clone(&self.heartbeat_interval),
-            last_heartbeat_ack: Arc::clone(&self.last_heartbeat_ack),
+            *task_guard = Some(handle);
         }
     }
 }
@@ -2,22 +2,94 @@ use pyo3::prelude::*;
 use reqwest::{Client as ReqwestClient, header::{HeaderMap, HeaderValue, AUTHORIZATION, USER_AGENT}};
 use serde_json::Value;
 use std::sync::Arc;
-use tokio::sync::RwLock;
 use std::collections::HashMap;
+use tokio::sync::{RwLock, Mutex};
+use tokio::time::{sleep, Duration};
 use crate::errors::DiscordError;
 
 const API_BASE: &str = "https://discord.com/api/v10";
 const USER_AGENT_STRING: &str = "DiscordBot (discord.py-rust 3.0.0)";
 
+/// How many times a single request will transparently retry a 429 before
+/// giving up and raising `DiscordError::RateLimited`.
+const MAX_RATE_LIMIT_RETRIES: u32 = 5;
+
+/// If a bucket (or the global limit) won't free up within this many
+/// seconds, stop waiting and surface `RateLimited` instead of blocking.
+const MAX_RATE_LIMIT_WAIT: f64 = 60.0;
+
+/// Tracked state for a single rate limit bucket, as reported by Discord's
+/// `X-RateLimit-*` response headers.
+#[derive(Debug, Clone, Copy)]
+struct BucketState {
+    remaining: i64,
+    reset_at: f64,
+}
+
 #[derive(Clone)]
 pub struct HTTPClient {
     client: ReqwestClient,
+    api_base: String,
     token: Arc<RwLock<Option<String>>>,
-    rate_limits: Arc<RwLock<HashMap<String, f64>>>,
+    // Route template (e.g. "GET /channels/123/messages/:id") -> bucket hash.
+    route_buckets: Arc<RwLock<HashMap<String, String>>>,
+    // Bucket hash -> current remaining/reset_at.
+    bucket_states: Arc<RwLock<HashMap<String, BucketState>>>,
+    // Bucket hash -> mutex, so a burst of concurrent requests to the same
+    // bucket queue up instead of all observing `remaining > 0` at once.
+    bucket_locks: Arc<RwLock<HashMap<String, Arc<Mutex<()>>>>>,
+    // Shared gate for Discord's global rate limit, which applies across
+    // every bucket.
+    global_reset_at: Arc<RwLock<Option<f64>>>,
+}
+
+fn now_secs() -> f64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+/// Builds a route template for bucket tracking: major parameters
+/// (`channel_id`, `guild_id`, `webhook_id`) are kept as-is since Discord
+/// scopes buckets to them, while any other numeric ID segment is
+/// collapsed so e.g. two different message IDs share a bucket.
+fn route_template(method: &str, path: &str) -> String {
+    let mut templated = Vec::new();
+    let mut keep_next = false;
+
+    for part in path.split('/') {
+        if keep_next {
+            templated.push(part.to_string());
+            keep_next = false;
+            continue;
+        }
+
+        if matches!(part, "channels" | "guilds" | "webhooks") {
+            keep_next = true;
+            templated.push(part.to_string());
+            continue;
+        }
+
+        if !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()) {
+            templated.push(":id".to_string());
+        } else {
+            templated.push(part.to_string());
+        }
+    }
+
+    format!("{} {}", method, templated.join("/"))
 }
 
 impl HTTPClient {
     pub fn new() -> Result<Self, DiscordError> {
+        Self::with_api_base(API_BASE.to_string())
+    }
+
+    /// Construct an `HTTPClient` pointed at a self-hosted or alternate
+    /// Discord-compatible instance (e.g. a Spacebar server) instead of
+    /// discord.com.
+    pub fn with_api_base(api_base: String) -> Result<Self, DiscordError> {
         let mut headers = HeaderMap::new();
         headers.insert(USER_AGENT, HeaderValue::from_static(USER_AGENT_STRING));
 
@@ -27,59 +99,178 @@ impl HTTPClient {
 
         Ok(HTTPClient {
             client,
+            api_base,
             token: Arc::new(RwLock::new(None)),
-            rate_limits: Arc::new(RwLock::new(HashMap::new())),
+            route_buckets: Arc::new(RwLock::new(HashMap::new())),
+            bucket_states: Arc::new(RwLock::new(HashMap::new())),
+            bucket_locks: Arc::new(RwLock::new(HashMap::new())),
+            global_reset_at: Arc::new(RwLock::new(None)),
         })
     }
 
+    pub fn api_base(&self) -> &str {
+        &self.api_base
+    }
+
     pub async fn set_token(&self, token: String) {
         *self.token.write().await = Some(token);
     }
 
+    async fn bucket_lock_for(&self, bucket_hash: &str) -> Arc<Mutex<()>> {
+        if let Some(lock) = self.bucket_locks.read().await.get(bucket_hash) {
+            return Arc::clone(lock);
+        }
+        let mut locks = self.bucket_locks.write().await;
+        Arc::clone(locks.entry(bucket_hash.to_string()).or_insert_with(|| Arc::new(Mutex::new(()))))
+    }
+
+    /// Wait out the global rate limit, if one is currently in effect.
+    async fn wait_for_global(&self) -> Result<(), DiscordError> {
+        let reset_at = *self.global_reset_at.read().await;
+        if let Some(reset_at) = reset_at {
+            let wait = reset_at - now_secs();
+            if wait > 0.0 {
+                if wait > MAX_RATE_LIMIT_WAIT {
+                    return Err(DiscordError::RateLimited { retry_after: wait });
+                }
+                sleep(Duration::from_secs_f64(wait)).await;
+            }
+        }
+        Ok(())
+    }
+
+    /// Wait out the known state for `bucket_hash`, if it's currently
+    /// exhausted.
+    async fn wait_for_bucket(&self, bucket_hash: &str) -> Result<(), DiscordError> {
+        let state = self.bucket_states.read().await.get(bucket_hash).copied();
+        if let Some(state) = state {
+            if state.remaining <= 0 {
+                let wait = state.reset_at - now_secs();
+                if wait > 0.0 {
+                    if wait > MAX_RATE_LIMIT_WAIT {
+                        return Err(DiscordError::RateLimited { retry_after: wait });
+                    }
+                    sleep(Duration::from_secs_f64(wait)).await;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Record bucket/remaining/reset-after from a response's rate limit
+    /// headers, and return the bucket hash used (if any) for this route.
+    async fn record_headers(&self, route: &str, headers: &reqwest::header::HeaderMap) -> Option<String> {
+        let bucket_hash = headers
+            .get("x-ratelimit-bucket")
+            .and_then(|v| v.to_str().ok())
+            .map(|s| s.to_string());
+
+        if let Some(bucket_hash) = &bucket_hash {
+            self.route_buckets.write().await.insert(route.to_string(), bucket_hash.clone());
+
+            let remaining = headers
+                .get("x-ratelimit-remaining")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|s| s.parse::<f64>().ok())
+                .map(|v| v as i64);
+            let reset_after = headers
+                .get("x-ratelimit-reset-after")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|s| s.parse::<f64>().ok());
+
+            if let (Some(remaining), Some(reset_after)) = (remaining, reset_after) {
+                self.bucket_states.write().await.insert(
+                    bucket_hash.clone(),
+                    BucketState { remaining, reset_at: now_secs() + reset_after },
+                );
+            }
+        }
+
+        if headers.get("x-ratelimit-global").is_some() {
+            // The global header is only informational on success; the real
+            // signal is a 429 body with `global: true`, handled in `request`.
+        }
+
+        bucket_hash
+    }
+
     pub async fn request(
         &self,
         method: &str,
         path: &str,
         json: Option<Value>,
     ) -> Result<Value, DiscordError> {
-        let url = format!("{}{}", API_BASE, path);
-
-        let token = self.token.read().await.clone();
-        let mut req = match method {
-            "GET" => self.client.get(&url),
-            "POST" => self.client.post(&url),
-            "PUT" => self.client.put(&url),
-            "PATCH" => self.client.patch(&url),
-            "DELETE" => self.client.delete(&url),
-            _ => return Err(DiscordError::InvalidData(format!("Invalid HTTP method: {}", method))),
-        };
-
-        if let Some(token) = token {
-            req = req.header(AUTHORIZATION, format!("Bot {}", token));
-        }
+        let route = route_template(method, path);
+        let url = format!("{}{}", self.api_base, path);
 
-        if let Some(body) = json {
-            req = req.json(&body);
-        }
+        for attempt in 0..=MAX_RATE_LIMIT_RETRIES {
+            self.wait_for_global().await?;
 
-        let response = req.send().await?;
-        let status = response.status();
+            let bucket_hash = self.route_buckets.read().await.get(&route).cloned();
+            let bucket_guard = if let Some(bucket_hash) = &bucket_hash {
+                // Acquire the bucket's mutex before checking `remaining` so a
+                // burst of requests serializes and each one re-observes the
+                // state left by the request before it, instead of every
+                // request in the burst reading `remaining > 0` at once.
+                let guard = self.bucket_lock_for(bucket_hash).await.lock_owned().await;
+                self.wait_for_bucket(bucket_hash).await?;
+                Some(guard)
+            } else {
+                None
+            };
+
+            let token = self.token.read().await.clone();
+            let mut req = match method {
+                "GET" => self.client.get(&url),
+                "POST" => self.client.post(&url),
+                "PUT" => self.client.put(&url),
+                "PATCH" => self.client.patch(&url),
+                "DELETE" => self.client.delete(&url),
+                _ => return Err(DiscordError::InvalidData(format!("Invalid HTTP method: {}", method))),
+            };
+
+            if let Some(token) = token {
+                req = req.header(AUTHORIZATION, format!("Bot {}", token));
+            }
+
+            if let Some(body) = &json {
+                req = req.json(body);
+            }
+
+            let response = req.send().await?;
+            let status = response.status();
+            self.record_headers(&route, response.headers()).await;
+            drop(bucket_guard);
+
+            if status.is_success() {
+                return Ok(response.json().await?);
+            }
 
-        if status.is_success() {
-            Ok(response.json().await?)
-        } else {
             match status.as_u16() {
-                403 => Err(DiscordError::Forbidden),
-                404 => Err(DiscordError::NotFound),
+                403 => return Err(DiscordError::Forbidden),
+                404 => return Err(DiscordError::NotFound),
                 429 => {
-                    let json: Value = response.json().await?;
-                    let retry_after = json["retry_after"].as_f64().unwrap_or(1.0);
-                    Err(DiscordError::RateLimited { retry_after })
+                    let body: Value = response.json().await?;
+                    let retry_after = body["retry_after"].as_f64().unwrap_or(1.0);
+                    let is_global = body["global"].as_bool().unwrap_or(false);
+
+                    if is_global {
+                        *self.global_reset_at.write().await = Some(now_secs() + retry_after);
+                    }
+
+                    if retry_after > MAX_RATE_LIMIT_WAIT || attempt == MAX_RATE_LIMIT_RETRIES {
+                        return Err(DiscordError::RateLimited { retry_after });
+                    }
+
+                    sleep(Duration::from_secs_f64(retry_after)).await;
+                    continue;
                 }
-                500..=599 => Err(DiscordError::ServerError),
-                _ => Err(DiscordError::InvalidData(format!("HTTP error: {}", status))),
+                500..=599 => return Err(DiscordError::ServerError),
+                _ => return Err(DiscordError::InvalidData(format!("HTTP error: {}", status))),
             }
         }
+
+        Err(DiscordError::RateLimited { retry_after: MAX_RATE_LIMIT_WAIT })
     }
 
     pub async fn get_gateway(&self) -> Result<String, DiscordError> {
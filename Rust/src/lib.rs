@@ -109,6 +109,14 @@ fn discord(m: &Bound<'_, PyModule>) -> PyResult<()> {
     // Register client
     client::register_module(m)?;
 
+    // Register shard status types
+    shard::register_module(m)?;
+
+    // Register voice subsystem
+    opus::register_module(m)?;
+    oggparse::register_module(m)?;
+    voice_client::register_module(m)?;
+
     // Register state cache
     state::register_module(m)?;
 
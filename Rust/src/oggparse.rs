@@ -0,0 +1,114 @@
+use pyo3::prelude::*;
+use crate::errors::DiscordError;
+
+const CAPTURE_PATTERN: &[u8; 4] = b"OggS";
+const HEADER_TYPE_CONTINUED: u8 = 0x01;
+
+/// A single Ogg page's parsed header, plus the raw payload bytes that
+/// follow it, split into packets by the lacing table (the last one may be
+/// a packet that continues on the next page).
+struct OggPage<'a> {
+    continued: bool,
+    segments: Vec<&'a [u8]>,
+}
+
+fn read_page(data: &[u8]) -> Result<(OggPage<'_>, usize), DiscordError> {
+    if data.len() < 27 || &data[0..4] != CAPTURE_PATTERN {
+        return Err(DiscordError::InvalidData("Invalid Ogg page: missing capture pattern".to_string()));
+    }
+
+    let header_type = data[5];
+    let page_segments = data[26] as usize;
+    let segment_table_end = 27 + page_segments;
+
+    if data.len() < segment_table_end {
+        return Err(DiscordError::InvalidData("Truncated Ogg page segment table".to_string()));
+    }
+
+    let lacing_values = &data[27..segment_table_end];
+    let mut segments = Vec::new();
+    let mut offset = segment_table_end;
+
+    // Lacing values group into packets: consecutive 255s mean "more of
+    // this packet follows"; a value < 255 ends the packet. Each run is
+    // emitted here as one slice; a run still open at the end of the page
+    // is stitched onto the next page's first slice by the caller.
+    let mut run_start = offset;
+    let mut run_len = 0usize;
+    for &lacing in lacing_values {
+        run_len += lacing as usize;
+        if lacing < 255 {
+            segments.push(&data[run_start..run_start + run_len]);
+            offset = run_start + run_len;
+            run_start = offset;
+            run_len = 0;
+        }
+    }
+    if run_len > 0 {
+        // Packet continues into the next page.
+        segments.push(&data[run_start..run_start + run_len]);
+        offset = run_start + run_len;
+    }
+
+    Ok((OggPage { continued: header_type & HEADER_TYPE_CONTINUED != 0, segments }, offset))
+}
+
+/// Demux a complete Ogg-Opus byte stream into the raw Opus packets it
+/// contains, in order. The stream's first two packets are the `OpusHead`
+/// and `OpusTags` metadata packets; they are dropped, so only the audio
+/// packets are returned.
+pub fn extract_opus_packets(mut data: &[u8]) -> Result<Vec<Vec<u8>>, DiscordError> {
+    let mut packets: Vec<Vec<u8>> = Vec::new();
+    let mut pending: Option<Vec<u8>> = None;
+
+    while !data.is_empty() {
+        let (page, consumed) = read_page(data)?;
+
+        for (i, segment) in page.segments.iter().enumerate() {
+            let is_first_segment = i == 0;
+            if is_first_segment && page.continued {
+                if let Some(mut buf) = pending.take() {
+                    buf.extend_from_slice(segment);
+                    // Kept pending either way: if the packet runs on into
+                    // the next page, that page's continued flag appends to
+                    // it again; otherwise the next slice flushes it.
+                    pending = Some(buf);
+                } else {
+                    pending = Some(segment.to_vec());
+                }
+            } else if let Some(buf) = pending.take() {
+                packets.push(buf);
+                pending = Some(segment.to_vec());
+            } else {
+                pending = Some(segment.to_vec());
+            }
+        }
+
+        data = &data[consumed..];
+    }
+
+    if let Some(buf) = pending {
+        packets.push(buf);
+    }
+
+    // Drop OpusHead/OpusTags; callers only want audio frames.
+    if packets.len() >= 2 {
+        packets.drain(0..2);
+    }
+
+    Ok(packets)
+}
+
+#[pyfunction]
+fn parse_ogg_opus(py: Python, data: Vec<u8>) -> PyResult<Vec<Py<PyAny>>> {
+    let packets = extract_opus_packets(&data).map_err(PyErr::from)?;
+    Ok(packets
+        .into_iter()
+        .map(|p| pyo3::types::PyBytes::new_bound(py, &p).into())
+        .collect())
+}
+
+pub fn register_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(parse_ogg_opus, m)?)?;
+    Ok(())
+}
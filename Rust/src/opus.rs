@@ -0,0 +1,56 @@
+use pyo3::prelude::*;
+use audiopus::coder::{Decoder as OpusDecoder, Encoder as OpusEncoder};
+use audiopus::{Application, Channels, SampleRate};
+use crate::errors::DiscordError;
+
+/// Discord voice audio is always 48kHz stereo, 20ms frames (960 samples
+/// per channel per frame).
+pub const SAMPLE_RATE: u32 = 48_000;
+pub const CHANNELS: usize = 2;
+pub const FRAME_SAMPLES: usize = 960;
+
+/// Thin wrapper around libopus configured the way Discord expects:
+/// encodes PCM frames into Opus packets, and decodes received Opus
+/// packets back into PCM for `recv_audio`.
+pub struct OpusCodec {
+    encoder: OpusEncoder,
+    decoder: OpusDecoder,
+}
+
+impl OpusCodec {
+    pub fn new() -> Result<Self, DiscordError> {
+        let encoder = OpusEncoder::new(SampleRate::Hz48000, Channels::Stereo, Application::Audio)
+            .map_err(|e| DiscordError::InvalidData(format!("Failed to create Opus encoder: {}", e)))?;
+        let decoder = OpusDecoder::new(SampleRate::Hz48000, Channels::Stereo)
+            .map_err(|e| DiscordError::InvalidData(format!("Failed to create Opus decoder: {}", e)))?;
+
+        Ok(OpusCodec { encoder, decoder })
+    }
+
+    /// Encode one 20ms stereo PCM frame (`FRAME_SAMPLES * CHANNELS` i16
+    /// samples) into an Opus packet.
+    pub fn encode(&mut self, pcm: &[i16]) -> Result<Vec<u8>, DiscordError> {
+        let mut out = vec![0u8; 4000];
+        let len = self
+            .encoder
+            .encode(pcm, &mut out)
+            .map_err(|e| DiscordError::InvalidData(format!("Opus encode failed: {}", e)))?;
+        out.truncate(len);
+        Ok(out)
+    }
+
+    /// Decode a received Opus packet back into interleaved stereo PCM.
+    pub fn decode(&mut self, packet: &[u8]) -> Result<Vec<i16>, DiscordError> {
+        let mut out = vec![0i16; FRAME_SAMPLES * CHANNELS];
+        let len = self
+            .decoder
+            .decode(Some(packet), &mut out, false)
+            .map_err(|e| DiscordError::InvalidData(format!("Opus decode failed: {}", e)))?;
+        out.truncate(len * CHANNELS);
+        Ok(out)
+    }
+}
+
+pub fn register_module(_m: &Bound<'_, PyModule>) -> PyResult<()> {
+    Ok(())
+}
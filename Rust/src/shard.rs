@@ -0,0 +1,337 @@
+use crate::backoff::ExponentialBackoff;
+use crate::enums::Intents;
+use crate::errors::DiscordError;
+use crate::gateway::{Gateway, GatewayAction, GatewayCompression};
+use crate::http::HTTPClient;
+use dashmap::DashMap;
+use pyo3::prelude::*;
+use rand::Rng;
+use serde_json::Value;
+use std::sync::Arc;
+use tokio::time::{sleep, Duration};
+
+/// Connection status for a single shard, surfaced to Python through
+/// `Client.shards`.
+#[pyclass]
+#[derive(Clone, Debug)]
+pub struct ShardStatus {
+    #[pyo3(get)]
+    pub shard_id: u32,
+    #[pyo3(get)]
+    pub shard_count: u32,
+    #[pyo3(get)]
+    pub connected: bool,
+    #[pyo3(get)]
+    pub latency: Option<f64>,
+}
+
+#[pymethods]
+impl ShardStatus {
+    fn __repr__(&self) -> String {
+        format!(
+            "<ShardStatus id={} connected={} latency={:?}>",
+            self.shard_id, self.connected, self.latency
+        )
+    }
+}
+
+/// Drives one `Gateway` per shard and funnels their dispatches into a
+/// single shared handler, spacing IDENTIFYs across the buckets Discord's
+/// `session_start_limit.max_concurrency` allows.
+pub struct ShardManager {
+    shard_ids: Vec<u32>,
+    shard_count: u32,
+    max_concurrency: u32,
+    recommended_shards: u32,
+    compression: GatewayCompression,
+    statuses: Arc<DashMap<u32, ShardStatus>>,
+}
+
+impl ShardManager {
+    /// Build a `ShardManager` from `/gateway/bot`, optionally overridden by
+    /// the caller with an explicit shard count and/or a specific subset of
+    /// shard ids to run in this process.
+    pub async fn from_gateway_bot(
+        http: &HTTPClient,
+        shard_count_override: Option<u32>,
+        shard_ids_override: Option<Vec<u32>>,
+        gateway_url_override: Option<String>,
+        compression: GatewayCompression,
+    ) -> Result<(Self, String), DiscordError> {
+        let bot_info: Value = http.get_gateway_bot().await?;
+
+        let gateway_url = match gateway_url_override {
+            Some(url) => url,
+            None => bot_info["url"]
+                .as_str()
+                .map(|s| s.to_string())
+                .ok_or_else(|| {
+                    DiscordError::Gateway("No gateway URL in /gateway/bot response".to_string())
+                })?,
+        };
+
+        let recommended = bot_info["shards"].as_u64().unwrap_or(1) as u32;
+        let shard_count = shard_count_override.unwrap_or(recommended).max(1);
+
+        let max_concurrency = bot_info["session_start_limit"]["max_concurrency"]
+            .as_u64()
+            .unwrap_or(1) as u32;
+
+        let shard_ids = shard_ids_override.unwrap_or_else(|| (0..shard_count).collect());
+
+        let statuses = Arc::new(DashMap::new());
+        for &id in &shard_ids {
+            statuses.insert(
+                id,
+                ShardStatus {
+                    shard_id: id,
+                    shard_count,
+                    connected: false,
+                    latency: None,
+                },
+            );
+        }
+
+        Ok((
+            ShardManager {
+                shard_ids,
+                shard_count,
+                max_concurrency,
+                recommended_shards: recommended,
+                compression,
+                statuses,
+            },
+            gateway_url,
+        ))
+    }
+
+    pub fn statuses(&self) -> Arc<DashMap<u32, ShardStatus>> {
+        Arc::clone(&self.statuses)
+    }
+
+    /// The shard count Discord recommended in `/gateway/bot`, regardless of
+    /// how many shards this particular manager was told to run.
+    pub fn recommended_shards(&self) -> u32 {
+        self.recommended_shards
+    }
+
+    /// Which of Discord's `max_concurrency` identify buckets a shard falls
+    /// into. Shards in the same bucket must IDENTIFY five seconds apart;
+    /// shards in different buckets may IDENTIFY concurrently.
+    fn identify_bucket(&self, shard_id: u32) -> u32 {
+        shard_id % self.max_concurrency.max(1)
+    }
+
+    /// Run every configured shard to completion (i.e. forever, barring a
+    /// fatal error), dispatching events through `on_dispatch`. When
+    /// `reconnect` is true, recoverable disconnects are retried with an
+    /// exponential backoff instead of ending the shard. Returns once every
+    /// shard has stopped, with the first shard error if there was one.
+    pub async fn run<F>(
+        self: Arc<Self>,
+        token: String,
+        intents: Intents,
+        gateway_url: String,
+        reconnect: bool,
+        on_dispatch: F,
+    ) -> Result<(), DiscordError>
+    where
+        F: Fn(u32, &str, &Value) + Send + Sync + Clone + 'static,
+    {
+        let mut buckets: std::collections::HashMap<u32, Vec<u32>> =
+            std::collections::HashMap::new();
+        for &id in &self.shard_ids {
+            buckets
+                .entry(self.identify_bucket(id))
+                .or_default()
+                .push(id);
+        }
+
+        let mut handles = Vec::new();
+        for (_, mut ids) in buckets {
+            ids.sort_unstable();
+            let manager = Arc::clone(&self);
+            let token = token.clone();
+            let gateway_url = gateway_url.clone();
+            let on_dispatch = on_dispatch.clone();
+
+            handles.push(tokio::spawn(async move {
+                let mut shard_handles = Vec::new();
+                for id in ids {
+                    let manager = Arc::clone(&manager);
+                    let token = token.clone();
+                    let gateway_url = gateway_url.clone();
+                    let on_dispatch = on_dispatch.clone();
+
+                    // Shards sharing an identify bucket run concurrently with
+                    // each other but must wait 5s between their own IDENTIFYs.
+                    shard_handles.push(tokio::spawn(async move {
+                        let result = manager
+                            .run_shard_supervised(
+                                id,
+                                token,
+                                intents,
+                                gateway_url,
+                                reconnect,
+                                on_dispatch,
+                            )
+                            .await;
+                        if let Err(e) = &result {
+                            eprintln!("Shard {} terminated: {}", id, e);
+                        }
+                        result
+                    }));
+
+                    sleep(Duration::from_secs(5)).await;
+                }
+                shard_handles
+            }));
+        }
+
+        let mut first_error = None;
+        for handle in handles {
+            for shard in handle.await.unwrap_or_default() {
+                if let Ok(Err(e)) = shard.await {
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+
+        first_error.map_or(Ok(()), Err)
+    }
+
+    /// Runs `run_shard` in a loop over a single, persistent `Gateway` so
+    /// session state survives a disconnect, retrying recoverable errors
+    /// with an exponential backoff and bailing out immediately on fatal
+    /// ones (privileged intents missing, bad token, ...) or when
+    /// `reconnect` is disabled.
+    async fn run_shard_supervised<F>(
+        &self,
+        shard_id: u32,
+        token: String,
+        intents: Intents,
+        gateway_url: String,
+        reconnect: bool,
+        on_dispatch: F,
+    ) -> Result<(), DiscordError>
+    where
+        F: Fn(u32, &str, &Value) + Clone,
+    {
+        let mut backoff = ExponentialBackoff::new();
+        let gateway =
+            Gateway::new_sharded(token, intents, shard_id, self.shard_count, self.compression);
+        let mut resuming = false;
+        let mut connect_url = gateway_url.clone();
+
+        loop {
+            let result = self
+                .run_shard(
+                    shard_id,
+                    &gateway,
+                    &connect_url,
+                    resuming,
+                    &mut backoff,
+                    on_dispatch.clone(),
+                )
+                .await;
+
+            if let Some(mut status) = self.statuses.get_mut(&shard_id) {
+                status.connected = false;
+            }
+
+            match result {
+                Ok(()) => return Ok(()),
+                Err(e) if !reconnect || !e.is_recoverable() => return Err(e),
+                Err(e) => {
+                    // Prefer resuming the existing session on the next
+                    // attempt if one is still available; otherwise fall
+                    // back to a cold reconnect against the general URL.
+                    resuming = gateway.session_id().await.is_some();
+                    connect_url = if resuming {
+                        gateway
+                            .resume_gateway_url()
+                            .await
+                            .unwrap_or_else(|| gateway_url.clone())
+                    } else {
+                        gateway_url.clone()
+                    };
+
+                    let delay = backoff.next_delay();
+                    eprintln!(
+                        "Shard {} disconnected ({}), reconnecting in {:?}",
+                        shard_id, e, delay
+                    );
+                    sleep(delay).await;
+                }
+            }
+        }
+    }
+
+    async fn run_shard<F>(
+        &self,
+        shard_id: u32,
+        gateway: &Gateway,
+        connect_url: &str,
+        resuming: bool,
+        backoff: &mut ExponentialBackoff,
+        on_dispatch: F,
+    ) -> Result<(), DiscordError>
+    where
+        F: Fn(u32, &str, &Value),
+    {
+        gateway.set_resuming(resuming).await;
+        gateway.connect(connect_url).await?;
+
+        if let Some(mut status) = self.statuses.get_mut(&shard_id) {
+            status.connected = true;
+        }
+
+        loop {
+            if let Some(payload) = gateway.receive().await? {
+                let action = gateway.handle_payload(&payload).await?;
+
+                if payload["op"].as_u64() == Some(10) {
+                    gateway.start_heartbeat().await;
+                }
+
+                if payload["op"].as_u64() == Some(0) {
+                    if let Some(event_type) = payload["t"].as_str() {
+                        if event_type == "READY" || event_type == "RESUMED" {
+                            // Only a confirmed session gets to reset the
+                            // schedule; a socket that connects then
+                            // immediately closes must keep backing off.
+                            backoff.reset();
+                        }
+                        on_dispatch(shard_id, event_type, &payload["d"]);
+                    }
+                }
+
+                match action {
+                    GatewayAction::Resume => {
+                        let url = gateway
+                            .resume_gateway_url()
+                            .await
+                            .unwrap_or_else(|| connect_url.to_string());
+                        gateway.set_resuming(true).await;
+                        gateway.connect(&url).await?;
+                    }
+                    GatewayAction::ReIdentify => {
+                        // Discord told us this session is gone for good;
+                        // per the gateway docs, wait a randomized 1-5s
+                        // before starting a fresh one.
+                        let jitter_ms = rand::thread_rng().gen_range(1_000..=5_000);
+                        sleep(Duration::from_millis(jitter_ms)).await;
+                        gateway.set_resuming(false).await;
+                        gateway.connect(connect_url).await?;
+                    }
+                    GatewayAction::None => {}
+                }
+            }
+        }
+    }
+}
+
+pub fn register_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<ShardStatus>()?;
+    Ok(())
+}
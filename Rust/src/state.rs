@@ -1,18 +1,24 @@
-use pyo3::prelude::*;
-use dashmap::DashMap;
-use std::sync::Arc;
 use crate::_types::Snowflake;
+use dashmap::DashMap;
+use pyo3::prelude::*;
+use std::sync::{Arc, RwLock};
 
-/// Connection state that caches Discord entities
+/// Connection state that caches Discord entities, kept current by
+/// `apply_dispatch` as gateway events arrive so callers can read back
+/// guilds/channels/roles/members without re-fetching over HTTP.
 pub struct State {
-    // Cached guilds
+    // Cached guilds, including unavailable stubs seeded from READY
     guilds: Arc<DashMap<Snowflake, serde_json::Value>>,
     // Cached users
     users: Arc<DashMap<Snowflake, serde_json::Value>>,
     // Cached channels
     channels: Arc<DashMap<Snowflake, serde_json::Value>>,
+    // Cached roles, keyed by role id (each entry carries its own guild_id)
+    roles: Arc<DashMap<Snowflake, serde_json::Value>>,
+    // Cached members, keyed by (guild_id, user_id)
+    members: Arc<DashMap<(Snowflake, Snowflake), serde_json::Value>>,
     // Self user ID
-    user_id: Arc<tokio::sync::RwLock<Option<Snowflake>>>,
+    user_id: Arc<RwLock<Option<Snowflake>>>,
 }
 
 impl State {
@@ -21,7 +27,9 @@ impl State {
             guilds: Arc::new(DashMap::new()),
             users: Arc::new(DashMap::new()),
             channels: Arc::new(DashMap::new()),
-            user_id: Arc::new(tokio::sync::RwLock::new(None)),
+            roles: Arc::new(DashMap::new()),
+            members: Arc::new(DashMap::new()),
+            user_id: Arc::new(RwLock::new(None)),
         }
     }
 
@@ -53,19 +61,238 @@ impl State {
         self.channels.get(&id).map(|v| v.clone())
     }
 
-    pub async fn set_user_id(&self, id: Snowflake) {
-        *self.user_id.write().await = Some(id);
+    pub fn get_role(&self, id: Snowflake) -> Option<serde_json::Value> {
+        self.roles.get(&id).map(|v| v.clone())
+    }
+
+    pub fn get_member(&self, guild_id: Snowflake, user_id: Snowflake) -> Option<serde_json::Value> {
+        self.members.get(&(guild_id, user_id)).map(|v| v.clone())
     }
 
-    pub async fn get_user_id(&self) -> Option<Snowflake> {
-        *self.user_id.read().await
+    pub fn set_user_id(&self, id: Snowflake) {
+        *self.user_id.write().unwrap() = Some(id);
+    }
+
+    pub fn get_user_id(&self) -> Option<Snowflake> {
+        *self.user_id.read().unwrap()
     }
 
     pub fn clear(&self) {
         self.guilds.clear();
         self.users.clear();
         self.channels.clear();
+        self.roles.clear();
+        self.members.clear();
+    }
+
+    /// Keep the cache current with a raw gateway dispatch: `*_CREATE`
+    /// inserts, `*_UPDATE` merges changed fields into the existing entry
+    /// (or inserts if we haven't seen it before), and `*_DELETE` removes.
+    /// Event types the cache doesn't model are ignored.
+    pub fn apply_dispatch(&self, event_type: &str, data: &serde_json::Value) {
+        match event_type {
+            "READY" => {
+                self.seed_guilds(data);
+                if let Some(id) = snowflake_field(&data["user"], "id") {
+                    self.set_user_id(id);
+                    self.users.insert(id, data["user"].clone());
+                }
+            }
+
+            "GUILD_CREATE" => {
+                if let Some(id) = snowflake_field(data, "id") {
+                    self.seed_guild_sub_entities(id, data);
+                    self.guilds.insert(id, data.clone());
+                }
+            }
+            "GUILD_UPDATE" => {
+                if let Some(id) = snowflake_field(data, "id") {
+                    merge_entry(&self.guilds, id, data);
+                }
+            }
+            "GUILD_DELETE" => {
+                if let Some(id) = snowflake_field(data, "id") {
+                    // `unavailable: true` means an outage, not the bot
+                    // leaving, so keep the entry but flag it stale instead
+                    // of dropping it.
+                    if data["unavailable"].as_bool().unwrap_or(false) {
+                        if let Some(mut guild) = self.guilds.get_mut(&id) {
+                            if let serde_json::Value::Object(ref mut map) = *guild {
+                                map.insert(
+                                    "unavailable".to_string(),
+                                    serde_json::Value::Bool(true),
+                                );
+                            }
+                        }
+                    } else {
+                        self.guilds.remove(&id);
+                    }
+                }
+            }
+
+            "CHANNEL_CREATE" => {
+                if let Some(id) = snowflake_field(data, "id") {
+                    self.channels.insert(id, data.clone());
+                }
+            }
+            "CHANNEL_UPDATE" => {
+                if let Some(id) = snowflake_field(data, "id") {
+                    merge_entry(&self.channels, id, data);
+                }
+            }
+            "CHANNEL_DELETE" => {
+                if let Some(id) = snowflake_field(data, "id") {
+                    self.channels.remove(&id);
+                }
+            }
+
+            "GUILD_ROLE_CREATE" => {
+                if let Some(id) = snowflake_field(&data["role"], "id") {
+                    self.roles.insert(id, role_with_guild_id(data));
+                }
+            }
+            "GUILD_ROLE_UPDATE" => {
+                if let Some(id) = snowflake_field(&data["role"], "id") {
+                    merge_entry(&self.roles, id, &role_with_guild_id(data));
+                }
+            }
+            "GUILD_ROLE_DELETE" => {
+                if let Some(id) = snowflake_field(data, "role_id") {
+                    self.roles.remove(&id);
+                }
+            }
+
+            "GUILD_MEMBER_ADD" | "GUILD_MEMBER_UPDATE" => {
+                if let (Some(guild_id), Some(user_id)) = (
+                    snowflake_field(data, "guild_id"),
+                    snowflake_field(&data["user"], "id"),
+                ) {
+                    merge_entry(&self.members, (guild_id, user_id), data);
+                    self.users.insert(user_id, data["user"].clone());
+                }
+            }
+            "GUILD_MEMBER_REMOVE" => {
+                if let (Some(guild_id), Some(user_id)) = (
+                    snowflake_field(data, "guild_id"),
+                    snowflake_field(&data["user"], "id"),
+                ) {
+                    self.members.remove(&(guild_id, user_id));
+                }
+            }
+
+            _ => {}
+        }
+    }
+
+    /// `GUILD_CREATE`'s nested `channels`/`roles`/`members` arrays are the
+    /// only place most of a guild's entities ever arrive (a standalone
+    /// `CHANNEL_CREATE`/`GUILD_ROLE_CREATE`/`GUILD_MEMBER_ADD` only fires
+    /// for entities created *after* the bot joined). Seed the flat caches
+    /// from them so `get_channel`/`get_role`/`get_member` work immediately.
+    fn seed_guild_sub_entities(&self, guild_id: Snowflake, guild: &serde_json::Value) {
+        if let Some(channels) = guild["channels"].as_array() {
+            for channel in channels {
+                if let Some(id) = snowflake_field(channel, "id") {
+                    self.channels.insert(id, with_guild_id(channel, guild_id));
+                }
+            }
+        }
+
+        if let Some(roles) = guild["roles"].as_array() {
+            for role in roles {
+                if let Some(id) = snowflake_field(role, "id") {
+                    self.roles.insert(id, with_guild_id(role, guild_id));
+                }
+            }
+        }
+
+        if let Some(members) = guild["members"].as_array() {
+            for member in members {
+                if let Some(user_id) = snowflake_field(&member["user"], "id") {
+                    self.members
+                        .insert((guild_id, user_id), with_guild_id(member, guild_id));
+                    self.users.insert(user_id, member["user"].clone());
+                }
+            }
+        }
+    }
+
+    /// READY's `guilds` array only contains unavailable stubs
+    /// (`{id, unavailable: true}`); the full object arrives later in a
+    /// `GUILD_CREATE`. Seed them now so `get_guild` reports "known but
+    /// unavailable" rather than nothing until that arrives.
+    fn seed_guilds(&self, ready_data: &serde_json::Value) {
+        if let Some(guilds) = ready_data["guilds"].as_array() {
+            for guild in guilds {
+                if let Some(id) = snowflake_field(guild, "id") {
+                    self.guilds.entry(id).or_insert_with(|| guild.clone());
+                }
+            }
+        }
+    }
+}
+
+/// Merge `patch`'s fields into the cached entry for `key`, recursing into
+/// nested objects so an update payload that happens to omit a field
+/// doesn't wipe it from the cached copy. Inserts `patch` as-is if there's
+/// no existing entry yet.
+fn merge_entry<K: std::hash::Hash + Eq>(
+    map: &DashMap<K, serde_json::Value>,
+    key: K,
+    patch: &serde_json::Value,
+) {
+    match map.get_mut(&key) {
+        Some(mut existing) => merge_json(&mut existing, patch),
+        None => {
+            map.insert(key, patch.clone());
+        }
+    }
+}
+
+fn merge_json(base: &mut serde_json::Value, patch: &serde_json::Value) {
+    match (base, patch) {
+        (serde_json::Value::Object(base_map), serde_json::Value::Object(patch_map)) => {
+            for (k, v) in patch_map {
+                merge_json(
+                    base_map.entry(k.clone()).or_insert(serde_json::Value::Null),
+                    v,
+                );
+            }
+        }
+        (base, patch) => *base = patch.clone(),
+    }
+}
+
+fn snowflake_field(data: &serde_json::Value, field: &str) -> Option<Snowflake> {
+    data[field]
+        .as_str()
+        .and_then(|s| s.parse::<u64>().ok())
+        .map(Snowflake::new)
+}
+
+/// Clone `value` with a `guild_id` field set to `guild_id`, for entities
+/// nested inside a `GUILD_CREATE` payload that don't carry it themselves
+/// (unlike their standalone `*_CREATE` dispatch equivalents).
+fn with_guild_id(value: &serde_json::Value, guild_id: Snowflake) -> serde_json::Value {
+    let mut value = value.clone();
+    if let serde_json::Value::Object(ref mut map) = value {
+        map.insert(
+            "guild_id".to_string(),
+            serde_json::Value::String(guild_id.to_string()),
+        );
+    }
+    value
+}
+
+/// `GUILD_ROLE_CREATE`/`UPDATE` payloads are `{guild_id, role}`; flatten
+/// that into the role object itself so a cached role still knows which
+/// guild it belongs to once stored under its own id.
+fn role_with_guild_id(data: &serde_json::Value) -> serde_json::Value {
+    let mut role = data["role"].clone();
+    if let serde_json::Value::Object(ref mut map) = role {
+        map.insert("guild_id".to_string(), data["guild_id"].clone());
     }
+    role
 }
 
 pub fn register_module(_m: &Bound<'_, PyModule>) -> PyResult<()> {
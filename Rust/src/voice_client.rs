@@ -0,0 +1,582 @@
+use crate::errors::DiscordError;
+use crate::oggparse;
+use crate::opus::OpusCodec;
+use chacha20poly1305::aead::{generic_array::GenericArray, Aead, AeadCore, KeyInit, Payload};
+use chacha20poly1305::XChaCha20Poly1305;
+use futures::{SinkExt, StreamExt};
+use pyo3::prelude::*;
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::RwLock;
+use tokio::time::{interval, sleep, Duration};
+use tokio_tungstenite::{
+    connect_async, tungstenite::protocol::Message, MaybeTlsStream, WebSocketStream,
+};
+use xsalsa20poly1305::XSalsa20Poly1305;
+
+const VOICE_GATEWAY_VERSION: u8 = 4;
+
+/// Voice gateway opcodes, distinct from (and numbered differently than)
+/// the main `Gateway`'s opcode set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum VoiceOpcode {
+    Identify = 0,
+    SelectProtocol = 1,
+    Ready = 2,
+    Heartbeat = 3,
+    SessionDescription = 4,
+    Speaking = 5,
+    HeartbeatAck = 6,
+    Resume = 7,
+    Hello = 8,
+    Resumed = 9,
+}
+
+impl VoiceOpcode {
+    fn from_u64(value: u64) -> Option<Self> {
+        Some(match value {
+            0 => Self::Identify,
+            1 => Self::SelectProtocol,
+            2 => Self::Ready,
+            3 => Self::Heartbeat,
+            4 => Self::SessionDescription,
+            5 => Self::Speaking,
+            6 => Self::HeartbeatAck,
+            7 => Self::Resume,
+            8 => Self::Hello,
+            9 => Self::Resumed,
+            _ => return None,
+        })
+    }
+}
+
+/// Encryption mode negotiated with `SELECT_PROTOCOL`. `select` prefers
+/// `XChaCha20Poly1305RtpSize`, Discord's newer mode; `XSalsa20Poly1305`
+/// is kept for servers that haven't rolled it out yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EncryptionMode {
+    XSalsa20Poly1305,
+    XChaCha20Poly1305RtpSize,
+}
+
+impl EncryptionMode {
+    fn wire_name(self) -> &'static str {
+        match self {
+            EncryptionMode::XSalsa20Poly1305 => "xsalsa20_poly1305",
+            EncryptionMode::XChaCha20Poly1305RtpSize => "aead_xchacha20_poly1305_rtpsize",
+        }
+    }
+
+    fn select(available: &[String]) -> Self {
+        if available
+            .iter()
+            .any(|m| m == "aead_xchacha20_poly1305_rtpsize")
+        {
+            EncryptionMode::XChaCha20Poly1305RtpSize
+        } else {
+            EncryptionMode::XSalsa20Poly1305
+        }
+    }
+}
+
+struct VoiceSession {
+    ssrc: u32,
+    mode: EncryptionMode,
+    secret_key: [u8; 32],
+}
+
+/// Mirrors `Gateway`, but speaks Discord's voice websocket + UDP protocol
+/// instead of the main gateway: IDENTIFY, the IP-discovery/SELECT_PROTOCOL
+/// handshake, and encrypted RTP audio framing.
+struct VoiceGateway {
+    endpoint: String,
+    server_id: String,
+    user_id: String,
+    session_id: String,
+    token: String,
+    ws: Arc<RwLock<Option<WebSocketStream<MaybeTlsStream<TcpStream>>>>>,
+    udp: Arc<RwLock<Option<UdpSocket>>>,
+    session: Arc<RwLock<Option<VoiceSession>>>,
+    rtp_sequence: Arc<AtomicU32>,
+    rtp_timestamp: Arc<AtomicU32>,
+}
+
+impl VoiceGateway {
+    fn new(
+        endpoint: String,
+        server_id: String,
+        user_id: String,
+        session_id: String,
+        token: String,
+    ) -> Self {
+        VoiceGateway {
+            endpoint,
+            server_id,
+            user_id,
+            session_id,
+            token,
+            ws: Arc::new(RwLock::new(None)),
+            udp: Arc::new(RwLock::new(None)),
+            session: Arc::new(RwLock::new(None)),
+            rtp_sequence: Arc::new(AtomicU32::new(0)),
+            rtp_timestamp: Arc::new(AtomicU32::new(0)),
+        }
+    }
+
+    async fn send_json(&self, data: &Value) -> Result<(), DiscordError> {
+        let mut guard = self.ws.write().await;
+        if let Some(ws) = &mut *guard {
+            ws.send(Message::Text(data.to_string())).await?;
+        }
+        Ok(())
+    }
+
+    async fn send_identify(&self) -> Result<(), DiscordError> {
+        self.send_json(&json!({
+            "op": VoiceOpcode::Identify as u8,
+            "d": {
+                "server_id": self.server_id,
+                "user_id": self.user_id,
+                "session_id": self.session_id,
+                "token": self.token,
+            }
+        }))
+        .await
+    }
+
+    async fn send_resume(&self) -> Result<(), DiscordError> {
+        self.send_json(&json!({
+            "op": VoiceOpcode::Resume as u8,
+            "d": {
+                "server_id": self.server_id,
+                "session_id": self.session_id,
+                "token": self.token,
+            }
+        }))
+        .await
+    }
+
+    /// Connect the voice websocket and run the handshake (IDENTIFY or
+    /// RESUME, depending on `resuming`) through to SESSION_DESCRIPTION,
+    /// at which point audio can be sent.
+    async fn connect(&self, resuming: bool) -> Result<(), DiscordError> {
+        let url = format!(
+            "wss://{}/?v={}",
+            self.endpoint.trim_end_matches(":443"),
+            VOICE_GATEWAY_VERSION
+        );
+        let (ws_stream, _) = connect_async(url).await?;
+        *self.ws.write().await = Some(ws_stream);
+
+        if resuming {
+            self.send_resume().await?;
+        } else {
+            self.send_identify().await?;
+        }
+
+        loop {
+            let msg = {
+                let mut guard = self.ws.write().await;
+                let ws = guard
+                    .as_mut()
+                    .ok_or_else(|| DiscordError::Gateway("Voice socket closed".to_string()))?;
+                ws.next().await
+            };
+            let Some(msg) = msg else {
+                return Err(DiscordError::Gateway(
+                    "Voice socket closed before handshake completed".to_string(),
+                ));
+            };
+            let Message::Text(text) = msg? else { continue };
+            let payload: Value = serde_json::from_str(&text)?;
+            let op = payload["op"].as_u64().unwrap_or(u64::MAX);
+
+            match VoiceOpcode::from_u64(op) {
+                Some(VoiceOpcode::Hello) => {
+                    let interval_ms = payload["d"]["heartbeat_interval"].as_u64().unwrap_or(5000);
+                    self.start_heartbeat(interval_ms).await;
+                }
+                Some(VoiceOpcode::Ready) => {
+                    let ssrc = payload["d"]["ssrc"].as_u64().unwrap_or(0) as u32;
+                    let ip = payload["d"]["ip"].as_str().unwrap_or_default().to_string();
+                    let port = payload["d"]["port"].as_u64().unwrap_or(0) as u16;
+                    let modes: Vec<String> = payload["d"]["modes"]
+                        .as_array()
+                        .map(|arr| {
+                            arr.iter()
+                                .filter_map(|v| v.as_str().map(String::from))
+                                .collect()
+                        })
+                        .unwrap_or_default();
+                    let mode = EncryptionMode::select(&modes);
+
+                    let (external_ip, external_port) = self.discover_ip(&ip, port, ssrc).await?;
+                    self.send_json(&json!({
+                        "op": VoiceOpcode::SelectProtocol as u8,
+                        "d": {
+                            "protocol": "udp",
+                            "data": {
+                                "address": external_ip,
+                                "port": external_port,
+                                "mode": mode.wire_name(),
+                            }
+                        }
+                    }))
+                    .await?;
+
+                    *self.session.write().await = Some(VoiceSession {
+                        ssrc,
+                        mode,
+                        secret_key: [0u8; 32],
+                    });
+                }
+                Some(VoiceOpcode::SessionDescription) => {
+                    let key: Vec<u8> = payload["d"]["secret_key"]
+                        .as_array()
+                        .map(|arr| {
+                            arr.iter()
+                                .filter_map(|v| v.as_u64())
+                                .map(|v| v as u8)
+                                .collect()
+                        })
+                        .unwrap_or_default();
+
+                    if let Some(session) = self.session.write().await.as_mut() {
+                        if key.len() == 32 {
+                            session.secret_key.copy_from_slice(&key);
+                        }
+                    }
+                    return Ok(());
+                }
+                Some(VoiceOpcode::Resumed) => return Ok(()),
+                Some(VoiceOpcode::HeartbeatAck) | None => {}
+                _ => {}
+            }
+        }
+    }
+
+    async fn start_heartbeat(&self, interval_ms: u64) {
+        let ws = Arc::clone(&self.ws);
+        tokio::spawn(async move {
+            let mut ticker = interval(Duration::from_millis(interval_ms));
+            loop {
+                ticker.tick().await;
+                let heartbeat = json!({ "op": VoiceOpcode::Heartbeat as u8, "d": 0 });
+                let mut guard = ws.write().await;
+                match &mut *guard {
+                    Some(ws) if ws.send(Message::Text(heartbeat.to_string())).await.is_ok() => {}
+                    _ => break,
+                }
+            }
+        });
+    }
+
+    /// Send the UDP IP-discovery packet (type 0x1, length 70, ssrc,
+    /// zero-padded to 74 bytes) and parse Discord's reply for our
+    /// externally-visible address.
+    async fn discover_ip(
+        &self,
+        ip: &str,
+        port: u16,
+        ssrc: u32,
+    ) -> Result<(String, u16), DiscordError> {
+        let socket = UdpSocket::bind("0.0.0.0:0").await?;
+        let remote: SocketAddr = format!("{}:{}", ip, port)
+            .parse()
+            .map_err(|_| DiscordError::InvalidData("Invalid voice UDP address".to_string()))?;
+        socket.connect(remote).await?;
+
+        let mut packet = [0u8; 74];
+        packet[0..2].copy_from_slice(&1u16.to_be_bytes());
+        packet[2..4].copy_from_slice(&70u16.to_be_bytes());
+        packet[4..8].copy_from_slice(&ssrc.to_be_bytes());
+        socket.send(&packet).await?;
+
+        let mut buf = [0u8; 74];
+        let n = socket.recv(&mut buf).await?;
+        if n < 74 {
+            return Err(DiscordError::Gateway(
+                "Short IP discovery response".to_string(),
+            ));
+        }
+
+        let address_end = buf[8..74]
+            .iter()
+            .position(|&b| b == 0)
+            .map(|p| 8 + p)
+            .unwrap_or(72);
+        let external_ip = String::from_utf8_lossy(&buf[8..address_end]).to_string();
+        let external_port = u16::from_be_bytes([buf[72], buf[73]]);
+
+        *self.udp.write().await = Some(socket);
+        Ok((external_ip, external_port))
+    }
+
+    /// Encrypt and send one Opus packet as an RTP frame, advancing the
+    /// sequence number and timestamp for the next call.
+    async fn send_audio_frame(&self, opus_packet: &[u8]) -> Result<(), DiscordError> {
+        // Kept as a full u32 for the `_rtpsize` nonce counter below; only
+        // the RTP header's sequence field truncates it to u16.
+        let nonce_counter = self.rtp_sequence.fetch_add(1, Ordering::SeqCst);
+        let sequence = nonce_counter as u16;
+        let timestamp = self.rtp_timestamp.fetch_add(960, Ordering::SeqCst);
+
+        let session_guard = self.session.read().await;
+        let session = session_guard
+            .as_ref()
+            .ok_or_else(|| DiscordError::Gateway("Voice session not ready".to_string()))?;
+
+        let mut header = [0u8; 12];
+        header[0] = 0x80;
+        header[1] = 0x78;
+        header[2..4].copy_from_slice(&sequence.to_be_bytes());
+        header[4..8].copy_from_slice(&timestamp.to_be_bytes());
+        header[8..12].copy_from_slice(&session.ssrc.to_be_bytes());
+
+        let packet = match session.mode {
+            EncryptionMode::XSalsa20Poly1305 => {
+                // Nonce is the RTP header, zero-padded to 24 bytes, and is
+                // not itself transmitted (the receiver reconstructs it).
+                let mut nonce = [0u8; 24];
+                nonce[..12].copy_from_slice(&header);
+
+                let cipher =
+                    XSalsa20Poly1305::new_from_slice(&session.secret_key).map_err(|e| {
+                        DiscordError::InvalidData(format!("Invalid voice secret key: {}", e))
+                    })?;
+                let ciphertext = cipher
+                    .encrypt(GenericArray::from_slice(&nonce), opus_packet)
+                    .map_err(|e| {
+                        DiscordError::InvalidData(format!("Voice encryption failed: {}", e))
+                    })?;
+
+                let mut packet = Vec::with_capacity(header.len() + ciphertext.len());
+                packet.extend_from_slice(&header);
+                packet.extend_from_slice(&ciphertext);
+                packet
+            }
+            EncryptionMode::XChaCha20Poly1305RtpSize => {
+                // `_rtpsize` modes append an explicit 4-byte nonce counter
+                // after the ciphertext, zero-padded up to the cipher's
+                // 24-byte nonce size. This must stay a full u32 (not the
+                // u16 RTP sequence) or it wraps and reuses a nonce every
+                // 65536 packets under the same secret_key.
+                let mut nonce = [0u8; 24];
+                nonce[..4].copy_from_slice(&nonce_counter.to_be_bytes());
+
+                let cipher =
+                    XChaCha20Poly1305::new_from_slice(&session.secret_key).map_err(|e| {
+                        DiscordError::InvalidData(format!("Invalid voice secret key: {}", e))
+                    })?;
+                // The unencrypted RTP header is authenticated as AEAD
+                // associated data, per the `_rtpsize` modes' spec.
+                let ciphertext = cipher
+                    .encrypt(
+                        GenericArray::from_slice(&nonce),
+                        Payload { msg: opus_packet, aad: &header },
+                    )
+                    .map_err(|e| {
+                        DiscordError::InvalidData(format!("Voice encryption failed: {}", e))
+                    })?;
+
+                let mut packet = Vec::with_capacity(header.len() + ciphertext.len() + 4);
+                packet.extend_from_slice(&header);
+                packet.extend_from_slice(&ciphertext);
+                packet.extend_from_slice(&nonce_counter.to_be_bytes());
+                packet
+            }
+        };
+
+        let udp_guard = self.udp.read().await;
+        let udp = udp_guard
+            .as_ref()
+            .ok_or_else(|| DiscordError::Gateway("Voice UDP socket not connected".to_string()))?;
+        udp.send(&packet).await?;
+
+        Ok(())
+    }
+
+    /// Receive one RTP packet and decrypt it back down to an Opus packet.
+    async fn recv_audio_frame(&self) -> Result<Vec<u8>, DiscordError> {
+        let udp_guard = self.udp.read().await;
+        let udp = udp_guard
+            .as_ref()
+            .ok_or_else(|| DiscordError::Gateway("Voice UDP socket not connected".to_string()))?;
+
+        let mut buf = [0u8; 4096];
+        let n = udp.recv(&mut buf).await?;
+        if n < 12 {
+            return Err(DiscordError::Gateway("Short RTP packet".to_string()));
+        }
+        let header = &buf[0..12];
+
+        let session_guard = self.session.read().await;
+        let session = session_guard
+            .as_ref()
+            .ok_or_else(|| DiscordError::Gateway("Voice session not ready".to_string()))?;
+
+        match session.mode {
+            EncryptionMode::XSalsa20Poly1305 => {
+                let mut nonce = [0u8; 24];
+                nonce[..12].copy_from_slice(header);
+                let cipher =
+                    XSalsa20Poly1305::new_from_slice(&session.secret_key).map_err(|e| {
+                        DiscordError::InvalidData(format!("Invalid voice secret key: {}", e))
+                    })?;
+                cipher
+                    .decrypt(GenericArray::from_slice(&nonce), &buf[12..n])
+                    .map_err(|e| {
+                        DiscordError::InvalidData(format!("Voice decryption failed: {}", e))
+                    })
+            }
+            EncryptionMode::XChaCha20Poly1305RtpSize => {
+                if n < 16 {
+                    return Err(DiscordError::Gateway("Short RTP packet".to_string()));
+                }
+                let nonce_bytes = &buf[n - 4..n];
+                let mut nonce = [0u8; 24];
+                nonce[..4].copy_from_slice(nonce_bytes);
+                let cipher =
+                    XChaCha20Poly1305::new_from_slice(&session.secret_key).map_err(|e| {
+                        DiscordError::InvalidData(format!("Invalid voice secret key: {}", e))
+                    })?;
+                cipher
+                    .decrypt(
+                        GenericArray::from_slice(&nonce),
+                        Payload { msg: &buf[12..n - 4], aad: header },
+                    )
+                    .map_err(|e| {
+                        DiscordError::InvalidData(format!("Voice decryption failed: {}", e))
+                    })
+            }
+        }
+    }
+}
+
+/// Python-facing handle for a voice connection: connect, play an
+/// OGG-Opus source, and pull back decoded PCM from `recv_audio`.
+#[pyclass]
+pub struct VoiceClient {
+    gateway: Arc<VoiceGateway>,
+    playing: Arc<RwLock<bool>>,
+    paused: Arc<RwLock<bool>>,
+    decoder: Arc<RwLock<Option<OpusCodec>>>,
+}
+
+#[pymethods]
+impl VoiceClient {
+    #[new]
+    #[pyo3(signature = (endpoint, server_id, user_id, session_id, token))]
+    fn new(
+        endpoint: String,
+        server_id: String,
+        user_id: String,
+        session_id: String,
+        token: String,
+    ) -> Self {
+        VoiceClient {
+            gateway: Arc::new(VoiceGateway::new(
+                endpoint, server_id, user_id, session_id, token,
+            )),
+            playing: Arc::new(RwLock::new(false)),
+            paused: Arc::new(RwLock::new(false)),
+            decoder: Arc::new(RwLock::new(None)),
+        }
+    }
+
+    /// Connect to the voice websocket, IDENTIFY, and run IP discovery so
+    /// the client is ready to send audio.
+    fn connect<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let gateway = Arc::clone(&self.gateway);
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            gateway.connect(false).await.map_err(PyErr::from)
+        })
+    }
+
+    /// Parse `source` (raw OGG-Opus bytes) and stream it over the voice
+    /// UDP socket on a 20ms frame schedule.
+    fn play<'py>(&self, py: Python<'py>, source: Vec<u8>) -> PyResult<Bound<'py, PyAny>> {
+        let gateway = Arc::clone(&self.gateway);
+        let playing = Arc::clone(&self.playing);
+        let paused = Arc::clone(&self.paused);
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            Self::do_play(gateway, playing, paused, source)
+                .await
+                .map_err(PyErr::from)
+        })
+    }
+
+    /// Receive and decode the next audio frame from the voice socket as
+    /// raw PCM bytes (16-bit stereo, little-endian).
+    fn recv_audio<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let gateway = Arc::clone(&self.gateway);
+        let decoder = Arc::clone(&self.decoder);
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            let packet = gateway.recv_audio_frame().await.map_err(PyErr::from)?;
+
+            let mut decoder_guard = decoder.write().await;
+            if decoder_guard.is_none() {
+                *decoder_guard = Some(OpusCodec::new().map_err(PyErr::from)?);
+            }
+            let pcm = decoder_guard
+                .as_mut()
+                .unwrap()
+                .decode(&packet)
+                .map_err(PyErr::from)?;
+
+            let bytes: Vec<u8> = pcm.iter().flat_map(|s| s.to_le_bytes()).collect();
+            Ok(bytes)
+        })
+    }
+
+    fn pause(&self) {
+        *self.paused.blocking_write() = true;
+    }
+
+    fn resume(&self) {
+        *self.paused.blocking_write() = false;
+    }
+
+    fn stop(&self) {
+        *self.playing.blocking_write() = false;
+    }
+}
+
+impl VoiceClient {
+    async fn do_play(
+        gateway: Arc<VoiceGateway>,
+        playing: Arc<RwLock<bool>>,
+        paused: Arc<RwLock<bool>>,
+        source: Vec<u8>,
+    ) -> Result<(), DiscordError> {
+        let packets = oggparse::extract_opus_packets(&source)?;
+
+        *playing.write().await = true;
+        *paused.write().await = false;
+
+        let mut ticker = interval(Duration::from_millis(20));
+
+        for packet in packets {
+            if !*playing.read().await {
+                break;
+            }
+            while *paused.read().await {
+                sleep(Duration::from_millis(20)).await;
+            }
+
+            ticker.tick().await;
+            gateway.send_audio_frame(&packet).await?;
+        }
+
+        *playing.write().await = false;
+        Ok(())
+    }
+}
+
+pub fn register_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<VoiceClient>()?;
+    Ok(())
+}